@@ -1,6 +1,10 @@
+#[cfg(test)]
+use core::hash::Hash;
 use core::marker::PhantomData;
+#[cfg(test)]
+use std::collections::HashMap;
 use crossbeam_epoch::Guard;
-use crate::lock::{Lock, RawLock};
+use crate::lock::{Lock, RawLock, RawRwLock, RwLock};
 use rand::{distributions::Alphanumeric, rngs::ThreadRng, Rng};
 
 /// Trait for a sequential key-value map.
@@ -16,6 +20,26 @@ pub trait SequentialMap<K: ?Sized, V> {
     fn delete(&mut self, key: &K) -> Result<V, ()>;
 }
 
+/// Lets the tests use `HashMap` as the sequential map of concurrent wrappers.
+#[cfg(test)]
+impl<K: Clone + Eq + Hash, V> SequentialMap<K, V> for HashMap<K, V> {
+    fn lookup<'a>(&'a self, key: &'a K) -> Option<&'a V> {
+        self.get(key)
+    }
+
+    fn insert<'a>(&'a mut self, key: &'a K, value: V) -> Result<(), V> {
+        if self.contains_key(key) {
+            return Err(value);
+        }
+        let _ = HashMap::insert(self, key.clone(), value);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &K) -> Result<V, ()> {
+        self.remove(key).ok_or(())
+    }
+}
+
 /// Trait for a concurrent key-value map.
 pub trait ConcurrentMap<K: ?Sized, V> {
     /// Lookups a key.
@@ -65,6 +89,26 @@ where
     }
 }
 
+impl<K: ?Sized, V, L: RawRwLock, M> ConcurrentMap<K, V> for RwLock<L, M>
+where
+    M: SequentialMap<K, V>,
+{
+    fn lookup<'a, F, R>(&'a self, key: &'a K, _guard: &'a Guard, f: F) -> R
+    where
+        F: FnOnce(Option<&V>) -> R,
+    {
+        f(self.read().lookup(key))
+    }
+
+    fn insert<'a>(&'a self, key: &'a K, value: V, _guard: &'a Guard) -> Result<(), V> {
+        self.write().insert(key, value)
+    }
+
+    fn delete(&self, key: &K, _guard: &Guard) -> Result<V, ()> {
+        self.write().delete(key)
+    }
+}
+
 /// Converts nonblocking map into concurrent map
 #[derive(Default, Debug)]
 pub struct NonblockingConcurrentMap<K: ?Sized, V: Clone, M: NonblockingMap<K, V>> {
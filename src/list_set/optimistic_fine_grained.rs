@@ -140,6 +140,7 @@ impl<T: Ord + std::fmt::Display> OptimisticFineGrainedListSet<T> {
             if let Ok(r) = cur.find(key, guard) {
                 return Ok((r, cur));
             }
+            cur.prev.finish();
         }
     }
 }
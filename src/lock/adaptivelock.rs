@@ -0,0 +1,170 @@
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{self, MutexGuard, PoisonError};
+use std::time::Instant;
+
+use crossbeam_utils::Backoff;
+
+use crate::lock::*;
+
+const UNLOCKED: u8 = 0;
+const LOCKED: u8 = 1;
+/// Locked, and some waiters may be parked.
+const PARKED: u8 = 2;
+
+/// A spin lock that measures its recent hold times, and parks its waiters instead of spinning when
+/// critical sections are long.
+///
+/// Spinning is cheaper while critical sections are short and every thread runs on its own core,
+/// but wastes the processor of a descheduled holder when the threads outnumber the cores. Since
+/// long hold times are a symptom of both long critical sections and oversubscription, waiters spin
+/// only while the moving average of the hold times is short.
+#[derive(Debug, Default)]
+pub struct AdaptiveSpinLock {
+    state: AtomicU8,
+    /// The exponential moving average of the hold times in nanoseconds.
+    hold_nanos: AtomicU64,
+    // Parked waiters hold `inner` from marking the lock `PARKED` until they wait on `cond`, and
+    // unlockers take `inner` before notifying. Hence no notification is lost in between.
+    inner: sync::Mutex<()>,
+    cond: sync::Condvar,
+}
+
+impl AdaptiveSpinLock {
+    /// The average hold time up to which waiters spin.
+    const SPIN_LIMIT_NANOS: u64 = 20_000;
+
+    /// Creates a new adaptive spin lock.
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(UNLOCKED),
+            hold_nanos: AtomicU64::new(0),
+            inner: sync::Mutex::new(()),
+            cond: sync::Condvar::new(),
+        }
+    }
+
+    /// Returns whether waiters currently spin rather than park.
+    pub fn is_spinning(&self) -> bool {
+        self.hold_nanos.load(Ordering::Relaxed) <= Self::SPIN_LIMIT_NANOS
+    }
+
+    fn inner(&self) -> MutexGuard<'_, ()> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn try_acquire(&self) -> bool {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+impl RawLock for AdaptiveSpinLock {
+    /// When the lock was acquired.
+    type Token = Instant;
+
+    fn lock(&self) -> Instant {
+        if self.is_spinning() {
+            let backoff = Backoff::new();
+            while !backoff.is_completed() {
+                if self.try_acquire() {
+                    return Instant::now();
+                }
+
+                backoff.spin();
+            }
+        } else if self.try_acquire() {
+            return Instant::now();
+        }
+
+        let mut inner = self.inner();
+        while self.state.swap(PARKED, Ordering::Acquire) != UNLOCKED {
+            inner = self
+                .cond
+                .wait(inner)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+
+        Instant::now()
+    }
+
+    unsafe fn unlock(&self, token: Instant) {
+        let held = u64::try_from(token.elapsed().as_nanos()).unwrap_or(u64::MAX);
+
+        // Only the holder updates the average, so no update is lost.
+        let average = self.hold_nanos.load(Ordering::Relaxed);
+        self.hold_nanos
+            .store(average - average / 8 + held / 8, Ordering::Relaxed);
+
+        if self.state.swap(UNLOCKED, Ordering::Release) == PARKED {
+            let _inner = self.inner();
+            self.cond.notify_one();
+        }
+    }
+}
+
+impl RawTryLock for AdaptiveSpinLock {
+    fn try_lock(&self) -> Result<Instant, ()> {
+        if self.try_acquire() {
+            Ok(Instant::now())
+        } else {
+            Err(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+    use std::thread::{self, scope};
+
+    use super::super::api;
+    use super::adaptivelock::AdaptiveSpinLock;
+    use crate::lock::{Lock, RawLock};
+
+    #[test]
+    fn smoke() {
+        api::tests::smoke::<AdaptiveSpinLock>();
+    }
+
+    #[test]
+    fn park() {
+        const THREADS: usize = 4;
+        const STEPS: usize = 64;
+
+        let lock = Lock::<AdaptiveSpinLock, usize>::new(0);
+
+        scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..STEPS {
+                        let mut guard = lock.lock();
+                        thread::sleep(Duration::from_micros(100));
+                        *guard += 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(lock.into_inner(), THREADS * STEPS);
+    }
+
+    #[test]
+    fn adapt() {
+        let lock = AdaptiveSpinLock::new();
+        assert!(lock.is_spinning());
+
+        for _ in 0..16 {
+            let token = lock.lock();
+            thread::sleep(Duration::from_millis(1));
+            unsafe { lock.unlock(token) };
+        }
+        assert!(!lock.is_spinning());
+
+        for _ in 0..128 {
+            let token = lock.lock();
+            unsafe { lock.unlock(token) };
+        }
+        assert!(lock.is_spinning());
+    }
+}
@@ -1,6 +1,12 @@
 use core::cell::UnsafeCell;
+use core::marker::PhantomData;
 use core::mem::{self, ManuallyDrop};
 use core::ops::{Deref, DerefMut};
+use core::panic::{AssertUnwindSafe, Location};
+use core::time::Duration;
+use std::panic;
+
+use super::lockorder::LockOrder;
 
 /// Raw lock interface.
 pub trait RawLock: Default + Send + Sync {
@@ -24,23 +30,40 @@ pub trait RawTryLock: RawLock {
     fn try_lock(&self) -> Result<Self::Token, ()>;
 }
 
+/// Raw lock interface for the try_lock_for API.
+pub trait RawTimedLock: RawTryLock {
+    /// Tries to acquire the raw lock, giving up after the given timeout.
+    fn try_lock_for(&self, timeout: Duration) -> Result<Self::Token, ()>;
+}
+
 /// A type-safe lock.
+///
+/// In debug builds, acquiring the lock while holding locks that were acquired while holding it
+/// before, possibly transitively, panics with the backtraces of both acquisitions.
 #[repr(C)]
 #[derive(Debug)]
 pub struct Lock<L: RawLock, T> {
     lock: L,
     data: UnsafeCell<T>,
+    order: LockOrder,
 }
 
 unsafe impl<L: RawLock, T: Send> Send for Lock<L, T> {}
 unsafe impl<L: RawLock, T: Send> Sync for Lock<L, T> {}
 
+impl<L: RawLock, T: Default> Default for Lock<L, T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
 impl<L: RawLock, T> Lock<L, T> {
     /// Creates a new lock.
     pub fn new(data: T) -> Self {
         Self {
             lock: L::default(),
             data: UnsafeCell::new(data),
+            order: LockOrder::default(),
         }
     }
 
@@ -49,9 +72,17 @@ impl<L: RawLock, T> Lock<L, T> {
         self.data.into_inner()
     }
 
+    /// Returns the underlying raw lock.
+    pub fn raw_lock(&self) -> &L {
+        &self.lock
+    }
+
     /// Acquires the lock and dereferences the inner value.
+    #[track_caller]
     pub fn lock(&self) -> LockGuard<L, T> {
+        let acquiring = self.order.lock(Location::caller());
         let token = self.lock.lock();
+        self.order.acquired(acquiring);
         LockGuard {
             lock: self,
             token: ManuallyDrop::new(token),
@@ -61,8 +92,26 @@ impl<L: RawLock, T> Lock<L, T> {
 
 impl<L: RawTryLock, T> Lock<L, T> {
     /// Tries to acquire the lock and dereferences the inner value.
+    #[track_caller]
     pub fn try_lock(&self) -> Result<LockGuard<L, T>, ()> {
-        self.lock.try_lock().map(|token| LockGuard {
+        let acquiring = self.order.try_lock(Location::caller());
+        let token = self.lock.try_lock()?;
+        self.order.acquired(acquiring);
+        Ok(LockGuard {
+            lock: self,
+            token: ManuallyDrop::new(token),
+        })
+    }
+}
+
+impl<L: RawTimedLock, T> Lock<L, T> {
+    /// Tries to acquire the lock within the given timeout and dereferences the inner value.
+    #[track_caller]
+    pub fn try_lock_for(&self, timeout: Duration) -> Result<LockGuard<'_, L, T>, ()> {
+        let acquiring = self.order.try_lock(Location::caller());
+        let token = self.lock.try_lock_for(timeout)?;
+        self.order.acquired(acquiring);
+        Ok(LockGuard {
             lock: self,
             token: ManuallyDrop::new(token),
         })
@@ -74,6 +123,8 @@ impl<L: RawLock, T> Lock<L, T> {
     ///
     /// The underlying lock should be actually acquired.
     pub unsafe fn unlock_unchecked(&self, token: L::Token) {
+        self.order.unlock();
+
         // SAFETY: Trivial from the safety contract.
         self.lock.unlock(token);
     }
@@ -129,7 +180,7 @@ impl<'s, L: RawLock, T> Drop for LockGuard<'s, L, T> {
 
         // SAFETY: since `self` was created with `lock` and it's `token`, the `token` given to
         // `unlock()` is correct.
-        unsafe { self.lock.lock.unlock(token) };
+        unsafe { self.lock.unlock_unchecked(token) };
     }
 }
 
@@ -152,13 +203,23 @@ impl<'s, L: RawLock, T> DerefMut for LockGuard<'s, L, T> {
 }
 
 impl<'s, L: RawLock, T> LockGuard<'s, L, T> {
-    /// Transforms a lock guard to an address.
+    /// Transforms a lock guard to an address, forgetting the token of the underlying lock.
     pub fn into_raw(self) -> usize {
         let ret = self.lock as *const _ as usize;
         mem::forget(self);
         ret
     }
 
+    /// Transforms a lock guard to an address and the token of the underlying lock.
+    pub fn into_raw_parts(self) -> (usize, L::Token) {
+        let mut this = ManuallyDrop::new(self);
+        let ret = this.lock as *const _ as usize;
+
+        // SAFETY: `this` is not dropped, so `this.token` is not used anymore.
+        let token = unsafe { ManuallyDrop::take(&mut this.token) };
+        (ret, token)
+    }
+
     /// # Safety
     ///
     /// The given arguments should be the data of a forgotten lock guard.
@@ -169,14 +230,146 @@ impl<'s, L: RawLock, T> LockGuard<'s, L, T> {
             token: ManuallyDrop::new(token),
         }
     }
+
+    /// Releases the lock while running `f`, and reacquires it afterwards.
+    ///
+    /// The lock is reacquired even if `f` panics, and the panic is resumed.
+    #[track_caller]
+    pub fn unlocked<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        // SAFETY: `self.token` is replaced with a fresh token below before it is used again.
+        let token = unsafe { ManuallyDrop::take(&mut self.token) };
+
+        // SAFETY: since `self` was created with `lock` and it's `token`, the `token` given to
+        // `unlock()` is correct.
+        unsafe { self.lock.unlock_unchecked(token) };
+
+        // Reacquire here rather than in a drop guard, so that the acquisition is tracked to the
+        // caller.
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        let acquiring = self.lock.order.lock(Location::caller());
+        self.token = ManuallyDrop::new(self.lock.lock.lock());
+        self.lock.order.acquired(acquiring);
+        result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    /// Makes a guard for a part of the lock-protected value, e.g. a field or an element.
+    ///
+    /// The new guard still holds the lock, and releases it when dropped.
+    pub fn map<U, F>(mut this: Self, f: F) -> MappedLockGuard<'s, L, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        let data = f(&mut this) as *mut U;
+        this.into_mapped(data)
+    }
+
+    /// Tries to make a guard for a part of the lock-protected value. Returns the original guard if
+    /// `f` returns `None`.
+    pub fn try_map<U, F>(mut this: Self, f: F) -> Result<MappedLockGuard<'s, L, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        match f(&mut this) {
+            Some(data) => {
+                let data = data as *mut U;
+                Ok(this.into_mapped(data))
+            }
+            None => Err(this),
+        }
+    }
+
+    fn into_mapped<U>(self, data: *mut U) -> MappedLockGuard<'s, L, U> {
+        let mut this = ManuallyDrop::new(self);
+
+        // SAFETY: `this` is not dropped, so `this.token` is not used anymore.
+        let token = unsafe { ManuallyDrop::take(&mut this.token) };
+        MappedLockGuard {
+            lock: &this.lock.lock,
+            order: &this.lock.order,
+            data,
+            token: ManuallyDrop::new(token),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// A guard that holds the lock and dereferences a part of the inner value.
+#[derive(Debug)]
+pub struct MappedLockGuard<'s, L: RawLock, U> {
+    lock: &'s L,
+    order: &'s LockOrder,
+    data: *mut U,
+    token: ManuallyDrop<L::Token>,
+    _marker: PhantomData<&'s mut U>,
+}
+
+unsafe impl<'s, L: RawLock, U: Send> Send for MappedLockGuard<'s, L, U> {}
+unsafe impl<'s, L: RawLock, U: Sync> Sync for MappedLockGuard<'s, L, U> {}
+
+impl<'s, L: RawLock, U> MappedLockGuard<'s, L, U> {
+    /// Makes a guard for a part of the already mapped value.
+    pub fn map<V, F>(mut this: Self, f: F) -> MappedLockGuard<'s, L, V>
+    where
+        F: FnOnce(&mut U) -> &mut V,
+    {
+        let data = f(&mut this) as *mut V;
+        let mut this = ManuallyDrop::new(this);
+
+        // SAFETY: `this` is not dropped, so `this.token` is not used anymore.
+        let token = unsafe { ManuallyDrop::take(&mut this.token) };
+        MappedLockGuard {
+            lock: this.lock,
+            order: this.order,
+            data,
+            token: ManuallyDrop::new(token),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'s, L: RawLock, U> Drop for MappedLockGuard<'s, L, U> {
+    fn drop(&mut self) {
+        // SAFETY: `self.token` is not used anymore in this function, and as we are `drop`ing
+        // `self`, it is not used anymore.
+        let token = unsafe { ManuallyDrop::take(&mut self.token) };
+
+        self.order.unlock();
+
+        // SAFETY: `self` was created from a `LockGuard` of `lock` and it's `token`, so the `token`
+        // given to `unlock()` is correct.
+        unsafe { self.lock.unlock(token) };
+    }
+}
+
+impl<'s, L: RawLock, U> Deref for MappedLockGuard<'s, L, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: Having a `MappedLockGuard` means the underlying lock is held, and `data` points
+        // into the lock-protected value.
+        unsafe { &*self.data }
+    }
+}
+
+impl<'s, L: RawLock, U> DerefMut for MappedLockGuard<'s, L, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: Having a `MappedLockGuard` means the underlying lock is held, and `data` points
+        // into the lock-protected value.
+        unsafe { &mut *self.data }
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
 
+    use std::collections::HashMap;
     use std::thread::scope;
 
-    use super::{Lock, RawLock};
+    use super::{Lock, LockGuard, MappedLockGuard, RawLock};
+    use crate::lock::SpinLock;
 
     pub fn smoke<L: RawLock>() {
         const LENGTH: usize = 1024;
@@ -196,4 +389,32 @@ pub mod tests {
         d.sort_unstable();
         assert_eq!(d, (1..LENGTH).collect::<Vec<usize>>());
     }
+
+    #[test]
+    fn map() {
+        let lock = Lock::<SpinLock, HashMap<u8, (usize, usize)>>::new(HashMap::new());
+        let _ = lock.lock().insert(1, (0, 0));
+
+        let entry = LockGuard::map(lock.lock(), |m| m.get_mut(&1).unwrap());
+        let mut field = MappedLockGuard::map(entry, |e| &mut e.1);
+        *field += 1;
+        assert!(lock.try_lock().is_err());
+        drop(field);
+
+        let missing = LockGuard::try_map(lock.lock(), |m| m.get_mut(&2));
+        assert!(missing.is_err());
+        drop(missing);
+
+        assert_eq!(lock.try_lock().unwrap()[&1], (0, 1));
+    }
+
+    #[test]
+    fn unlocked() {
+        let lock = Lock::<SpinLock, usize>::new(0);
+
+        let mut guard = lock.lock();
+        guard.unlocked(|| *lock.try_lock().unwrap() += 1);
+        assert_eq!(*guard, 1);
+        assert!(lock.try_lock().is_err());
+    }
 }
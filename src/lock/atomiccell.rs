@@ -0,0 +1,379 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem;
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, AtomicU8, Ordering};
+
+use crossbeam_utils::CachePadded;
+
+use crate::lock::seqlock::{atomic_read, atomic_write, NoPadding, RawSeqLock};
+
+/// The number of sequence locks shared by the cells that do not fit in native atomics. It is a
+/// prime, so that cells laid out at a regular stride spread over all locks.
+const STRIPES: usize = 67;
+
+/// Returns the sequence lock protecting the cell at the given address.
+fn stripe(addr: usize) -> &'static RawSeqLock {
+    static LOCKS: [CachePadded<RawSeqLock>; STRIPES] =
+        [const { CachePadded::new(RawSeqLock::new()) }; STRIPES];
+
+    &LOCKS[addr % STRIPES]
+}
+
+/// Returns whether a value of type `T` can be accessed as the atomic type `A`.
+const fn can_transmute<T, A>() -> bool {
+    mem::size_of::<T>() == mem::size_of::<A>() && mem::align_of::<T>() >= mem::align_of::<A>()
+}
+
+/// Evaluates `$native` with `$a` bound to the cell `$cell` as a native atomic if `T` fits one, or
+/// evaluates `$fallback` otherwise.
+macro_rules! atomic {
+    ($cell:expr, $a:ident, $native:expr, $fallback:expr) => {
+        'op: {
+            atomic!(@check 'op, $cell, AtomicU8, $a, $native);
+            atomic!(@check 'op, $cell, AtomicU16, $a, $native);
+            atomic!(@check 'op, $cell, AtomicU32, $a, $native);
+            atomic!(@check 'op, $cell, AtomicU64, $a, $native);
+            $fallback
+        }
+    };
+    (@check $op:lifetime, $cell:expr, $atomic:ty, $a:ident, $native:expr) => {
+        if can_transmute::<T, $atomic>() {
+            // SAFETY: `T` has the size and alignment of the atomic type, and the cell is only
+            // accessed with atomic operations of the same type.
+            let $a = unsafe { &*($cell.as_ptr() as *const $atomic) };
+            break $op ($native);
+        }
+    };
+}
+
+/// A mutable memory location for `Copy` values that can be shared among threads.
+///
+/// Values are accessed as integers, so the operations are only available for `NoPadding` types.
+///
+/// Operations use native atomics if `T` has the size and alignment of one. Otherwise they are
+/// protected by a sequence lock taken from a global table of locks: writers take the lock, but
+/// readers only validate that no writer intervened, so that readers never block writers.
+#[repr(transparent)]
+pub struct AtomicCell<T> {
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for AtomicCell<T> {}
+
+impl<T> AtomicCell<T> {
+    /// Creates a new atomic cell.
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Destroys the cell and retrieves the value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Dereferences the value.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Returns a raw pointer to the value.
+    pub fn as_ptr(&self) -> *mut T {
+        self.value.get()
+    }
+
+    /// Returns whether operations on cells of type `AtomicCell<T>` use native atomics, rather than
+    /// sequence locks.
+    pub const fn is_lock_free() -> bool {
+        can_transmute::<T, AtomicU8>()
+            || can_transmute::<T, AtomicU16>()
+            || can_transmute::<T, AtomicU32>()
+            || can_transmute::<T, AtomicU64>()
+    }
+
+    fn stripe(&self) -> &'static RawSeqLock {
+        stripe(self.as_ptr() as usize)
+    }
+}
+
+impl<T: NoPadding> AtomicCell<T> {
+    /// Loads the value.
+    pub fn load(&self) -> T {
+        atomic! {
+            self, a,
+            // SAFETY: `T` has the size of the atomic type and no padding.
+            unsafe { mem::transmute_copy(&a.load(Ordering::Acquire)) },
+            self.seq_load()
+        }
+    }
+
+    /// Stores the value.
+    pub fn store(&self, value: T) {
+        atomic! {
+            self, a,
+            // SAFETY: `T` has the size of the atomic type and no padding.
+            a.store(unsafe { mem::transmute_copy(&value) }, Ordering::Release),
+            {
+                let _ = self.seq_update(|_| Some(value));
+            }
+        }
+    }
+
+    /// Stores the value and returns the previous one.
+    pub fn swap(&self, value: T) -> T {
+        atomic! {
+            self, a,
+            // SAFETY: `T` has the size of the atomic type and no padding.
+            unsafe {
+                mem::transmute_copy(&a.swap(mem::transmute_copy(&value), Ordering::AcqRel))
+            },
+            match self.seq_update(|_| Some(value)) {
+                Ok(prev) => prev,
+                Err(_) => unreachable!(),
+            }
+        }
+    }
+
+    /// Reads the value under the sequence lock, retrying until no writer intervened.
+    fn seq_load(&self) -> T {
+        let lock = self.stripe();
+
+        loop {
+            let seq = lock.read_begin();
+
+            // SAFETY: `T` has no padding, the value is valid, and all concurrent writes to it are
+            // atomic.
+            let value = unsafe { atomic_read(self.as_ptr()) };
+
+            if lock.read_validate(seq) {
+                // SAFETY: no writer intervened, so `value` is a copy of a valid value.
+                return unsafe { value.assume_init() };
+            }
+        }
+    }
+
+    /// Calls `f` with the value under the writer's lock of the sequence lock, and stores the value
+    /// returned by `f` if any. Returns the previous value, or `Err` if `f` returned `None`.
+    fn seq_update<F>(&self, f: F) -> Result<T, T>
+    where
+        F: FnOnce(T) -> Option<T>,
+    {
+        /// Releases the writer's lock, even if `f` panics.
+        struct Unlock(&'static RawSeqLock, usize);
+
+        impl Drop for Unlock {
+            fn drop(&mut self) {
+                self.0.write_unlock(self.1);
+            }
+        }
+
+        let lock = self.stripe();
+        let _unlock = Unlock(lock, lock.write_lock());
+
+        // SAFETY: `T` has no padding, and we hold the writer's lock, so no one else writes to the
+        // value.
+        let prev = unsafe { atomic_read(self.as_ptr()).assume_init() };
+        match f(prev) {
+            Some(value) => {
+                // SAFETY: `T` has no padding, and we hold the writer's lock, so no one else writes
+                // to the value.
+                unsafe { atomic_write(self.as_ptr(), value) };
+                Ok(prev)
+            }
+            None => Err(prev),
+        }
+    }
+}
+
+impl<T: NoPadding + Eq> AtomicCell<T> {
+    /// Stores `new` if the value is equal to `current`. Returns the previous value, or `Err` with
+    /// the value if it is not equal to `current`.
+    pub fn compare_exchange(&self, current: T, new: T) -> Result<T, T> {
+        atomic! {
+            self, a,
+            {
+                // SAFETY: `T` has the size of the atomic type and no padding.
+                let (mut current_raw, new_raw) =
+                    unsafe { (mem::transmute_copy(&current), mem::transmute_copy(&new)) };
+
+                loop {
+                    match a.compare_exchange(
+                        current_raw,
+                        new_raw,
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    ) {
+                        Ok(_) => break Ok(current),
+                        Err(prev_raw) => {
+                            // SAFETY: `T` has the size of the atomic type and no padding.
+                            let prev = unsafe { mem::transmute_copy(&prev_raw) };
+                            if prev != current {
+                                break Err(prev);
+                            }
+
+                            // `prev` is equal to `current` but has a different representation, so
+                            // retry with the representation of `prev`.
+                            current_raw = prev_raw;
+                        }
+                    }
+                }
+            },
+            self.seq_update(|prev| if prev == current { Some(new) } else { None })
+        }
+    }
+
+    /// Repeatedly calls `f` with the value and stores the value returned by `f`, until the value
+    /// is not changed concurrently in between. Returns the previous value, or `Err` with the value
+    /// if `f` returned `None`.
+    pub fn fetch_update<F>(&self, mut f: F) -> Result<T, T>
+    where
+        F: FnMut(T) -> Option<T>,
+    {
+        let mut prev = self.load();
+        while let Some(next) = f(prev) {
+            match self.compare_exchange(prev, next) {
+                Ok(prev) => return Ok(prev),
+                Err(value) => prev = value,
+            }
+        }
+        Err(prev)
+    }
+}
+
+impl<T: Default> Default for AtomicCell<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for AtomicCell<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: NoPadding + fmt::Debug> fmt::Debug for AtomicCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtomicCell")
+            .field("value", &self.load())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread::scope;
+
+    use super::AtomicCell;
+    use crate::lock::NoPadding;
+
+    /// A value that does not fit in native atomics, whose fields are always equal.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    struct Triple([usize; 3]);
+
+    // SAFETY: `Triple` is an array of integers.
+    unsafe impl NoPadding for Triple {}
+
+    impl Triple {
+        fn new(v: usize) -> Self {
+            Self([v; 3])
+        }
+
+        fn is_consistent(&self) -> bool {
+            self.0.iter().all(|&v| v == self.0[0])
+        }
+    }
+
+    /// A value that does not fit in native atomics, whose comparison panics.
+    #[derive(Debug, Clone, Copy, Eq)]
+    struct Explosive([usize; 3]);
+
+    // SAFETY: `Explosive` is an array of integers.
+    unsafe impl NoPadding for Explosive {}
+
+    impl PartialEq for Explosive {
+        fn eq(&self, _other: &Self) -> bool {
+            panic!("boom");
+        }
+    }
+
+    #[test]
+    fn native() {
+        assert!(AtomicCell::<u8>::is_lock_free());
+        assert!(AtomicCell::<usize>::is_lock_free());
+
+        let cell = AtomicCell::new(1usize);
+        assert_eq!(cell.swap(2), 1);
+        assert_eq!(cell.compare_exchange(1, 3), Err(2));
+        assert_eq!(cell.compare_exchange(2, 3), Ok(2));
+        assert_eq!(cell.fetch_update(|_| None), Err(3));
+        cell.store(4);
+        assert_eq!(cell.load(), 4);
+    }
+
+    #[test]
+    fn fallback() {
+        assert!(!AtomicCell::<Triple>::is_lock_free());
+        assert!(!AtomicCell::<[u8; 3]>::is_lock_free());
+
+        let cell = AtomicCell::new(Triple::new(1));
+        assert_eq!(cell.swap(Triple::new(2)), Triple::new(1));
+        assert_eq!(
+            cell.compare_exchange(Triple::new(1), Triple::new(3)),
+            Err(Triple::new(2))
+        );
+        assert_eq!(
+            cell.compare_exchange(Triple::new(2), Triple::new(3)),
+            Ok(Triple::new(2))
+        );
+        assert_eq!(cell.fetch_update(|_| None), Err(Triple::new(3)));
+        cell.store(Triple::new(4));
+        assert_eq!(cell.load(), Triple::new(4));
+    }
+
+    #[test]
+    fn panicking_eq() {
+        let cell = AtomicCell::new(Explosive([1; 3]));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            cell.compare_exchange(Explosive([1; 3]), Explosive([2; 3]))
+        }));
+        assert!(result.is_err());
+
+        // The lock of the cell is released, so the cell and the others sharing the lock work.
+        cell.store(Explosive([3; 3]));
+        assert_eq!(cell.load().0, [3; 3]);
+    }
+
+    #[test]
+    fn counter() {
+        const THREADS: usize = 8;
+        const STEPS: usize = 4096;
+
+        let native = AtomicCell::new(0usize);
+        let fallback = AtomicCell::new(Triple::default());
+
+        scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..STEPS {
+                        native.fetch_update(|v| Some(v + 1)).unwrap();
+                        fallback
+                            .fetch_update(|t| Some(Triple::new(t.0[0] + 1)))
+                            .unwrap();
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..STEPS {
+                        assert!(fallback.load().is_consistent());
+                    }
+                });
+            }
+        });
+
+        assert_eq!(native.into_inner(), THREADS * STEPS);
+        assert_eq!(fallback.into_inner(), Triple::new(THREADS * STEPS));
+    }
+}
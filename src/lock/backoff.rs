@@ -0,0 +1,84 @@
+use core::hint;
+use core::time::Duration;
+use std::thread;
+
+/// A strategy to wait between failed attempts to acquire a lock.
+///
+/// A fresh strategy is created for each acquisition, so that it can escalate as the attempts fail.
+pub trait BackoffPolicy: Default {
+    /// Waits before the next attempt.
+    fn snooze(&mut self);
+}
+
+/// Spins for exponentially many iterations, then yields the thread once spinning is no longer
+/// worthwhile. This is `crossbeam_utils::Backoff::snooze()`.
+#[derive(Debug, Default)]
+pub struct ExponentialBackoff {
+    inner: crossbeam_utils::Backoff,
+}
+
+impl BackoffPolicy for ExponentialBackoff {
+    fn snooze(&mut self) {
+        self.inner.snooze();
+    }
+}
+
+/// Spins for exponentially many iterations up to a bound, and never gives up the processor.
+///
+/// Suitable when every thread has a dedicated core.
+#[derive(Debug, Default)]
+pub struct BoundedSpinBackoff {
+    step: u32,
+}
+
+impl BoundedSpinBackoff {
+    const SPIN_LIMIT: u32 = 6;
+}
+
+impl BackoffPolicy for BoundedSpinBackoff {
+    fn snooze(&mut self) {
+        for _ in 0..1 << self.step {
+            hint::spin_loop();
+        }
+
+        if self.step < Self::SPIN_LIMIT {
+            self.step += 1;
+        }
+    }
+}
+
+/// Yields the thread at each attempt.
+#[derive(Debug, Default)]
+pub struct YieldBackoff;
+
+impl BackoffPolicy for YieldBackoff {
+    fn snooze(&mut self) {
+        thread::yield_now();
+    }
+}
+
+/// Parks the thread for exponentially growing timeouts up to a bound.
+///
+/// No lock unparks its waiters on release, so this is a sleep: a waiter only wakes up when its
+/// timeout expires, up to about a millisecond plus the timer latency after the lock is released.
+/// Suitable when the threads outnumber the cores, so that spinning or yielding waiters would take
+/// the processor from the holder.
+#[derive(Debug, Default)]
+pub struct ParkBackoff {
+    step: u32,
+}
+
+impl ParkBackoff {
+    /// Timeouts grow up to 2^`PARK_LIMIT` microseconds.
+    const PARK_LIMIT: u32 = 10;
+}
+
+impl BackoffPolicy for ParkBackoff {
+    fn snooze(&mut self) {
+        thread::park_timeout(Duration::from_micros(1 << self.step));
+
+        if self.step < Self::PARK_LIMIT {
+            self.step += 1;
+        }
+    }
+}
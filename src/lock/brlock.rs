@@ -0,0 +1,176 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crossbeam_utils::{Backoff, CachePadded};
+
+use super::reentrantlock::current_thread_id;
+use crate::lock::*;
+
+/// A big-reader lock.
+///
+/// Each reader only touches its own cache-padded slot, so that readers on different cores do not
+/// contend on a cache line. In exchange, a writer sweeps every slot to wait for the readers. Since
+/// threads are mapped to slots by their identifiers, a slot counts the readers sharing it.
+#[derive(Debug)]
+pub struct BrLock {
+    writer: AtomicBool,
+    readers: Box<[CachePadded<AtomicUsize>]>,
+}
+
+impl BrLock {
+    /// The number of reader slots of a default lock.
+    pub const DEFAULT_SLOTS: usize = 64;
+
+    /// Creates a new big-reader lock with the given number of reader slots.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `slots` is 0.
+    pub fn with_slots(slots: usize) -> Self {
+        assert!(slots > 0, "a big-reader lock should have at least one slot");
+
+        Self {
+            writer: AtomicBool::new(false),
+            readers: (0..slots)
+                .map(|_| CachePadded::new(AtomicUsize::new(0)))
+                .collect(),
+        }
+    }
+}
+
+impl Default for BrLock {
+    fn default() -> Self {
+        Self::with_slots(Self::DEFAULT_SLOTS)
+    }
+}
+
+impl RawRwLock for BrLock {
+    /// The index of the reader's slot.
+    type ReadToken = usize;
+    type WriteToken = ();
+
+    fn read_lock(&self) -> usize {
+        let backoff = Backoff::new();
+
+        loop {
+            if let Ok(slot) = self.try_read_lock() {
+                return slot;
+            }
+
+            backoff.snooze();
+        }
+    }
+
+    unsafe fn read_unlock(&self, slot: usize) {
+        self.readers[slot].fetch_sub(1, Ordering::Release);
+    }
+
+    fn write_lock(&self) {
+        let backoff = Backoff::new();
+
+        while self
+            .writer
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            backoff.snooze();
+        }
+
+        // New readers see `writer` and back off, so wait for the current readers to leave.
+        for readers in self.readers.iter() {
+            while readers.load(Ordering::SeqCst) != 0 {
+                backoff.snooze();
+            }
+        }
+    }
+
+    unsafe fn write_unlock(&self, _token: ()) {
+        self.writer.store(false, Ordering::Release);
+    }
+}
+
+impl RawTryRwLock for BrLock {
+    fn try_read_lock(&self) -> Result<usize, ()> {
+        if self.writer.load(Ordering::Relaxed) {
+            return Err(());
+        }
+
+        let slot = current_thread_id() % self.readers.len();
+        let readers = &self.readers[slot];
+
+        // Either we see the writer, or the writer sees us.
+        readers.fetch_add(1, Ordering::SeqCst);
+        if self.writer.load(Ordering::SeqCst) {
+            readers.fetch_sub(1, Ordering::Relaxed);
+            return Err(());
+        }
+
+        Ok(slot)
+    }
+
+    fn try_write_lock(&self) -> Result<(), ()> {
+        self.writer
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
+            .map_err(|_| ())?;
+
+        if self
+            .readers
+            .iter()
+            .all(|readers| readers.load(Ordering::SeqCst) == 0)
+        {
+            return Ok(());
+        }
+
+        self.writer.store(false, Ordering::Relaxed);
+        Err(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::Ordering;
+    use std::thread::scope;
+
+    use super::super::rwlock;
+    use super::brlock::BrLock;
+    use crate::lock::RwLock;
+
+    #[test]
+    fn smoke() {
+        rwlock::tests::smoke::<BrLock>();
+    }
+
+    #[test]
+    fn log_concurrent() {
+        rwlock::tests::log_concurrent::<BrLock>();
+    }
+
+    #[test]
+    fn max_writer_wait() {
+        let _wait = rwlock::tests::max_writer_wait::<BrLock>();
+    }
+
+    #[test]
+    fn writer_preference() {
+        rwlock::tests::writer_preference::<BrLock>(|lock| lock.writer.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn try_lock() {
+        let lock = RwLock::<BrLock, usize>::new(0);
+
+        let r1 = lock.read();
+        let r2 = lock.try_read().unwrap();
+        assert!(lock.try_write().is_err());
+        scope(|s| {
+            s.spawn(|| assert!(lock.try_write().is_err()));
+        });
+        drop((r1, r2));
+
+        let w = lock.write();
+        assert!(lock.try_read().is_err());
+        assert!(lock.try_write().is_err());
+        drop(w);
+
+        assert!(lock.try_write().is_ok());
+    }
+}
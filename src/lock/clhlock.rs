@@ -0,0 +1,287 @@
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use core::time::Duration;
+use std::time::Instant;
+
+use crossbeam_utils::{Backoff, CachePadded};
+
+use crate::lock::*;
+
+#[derive(Debug)]
+struct Node {
+    locked: AtomicBool,
+}
+
+/// A CLH lock.
+///
+/// Each waiter spins on its predecessor's queue node.
+#[derive(Debug)]
+pub struct ClhLock {
+    tail: AtomicPtr<CachePadded<Node>>,
+}
+
+/// A CLH lock token, owning the acquirer's queue node.
+#[derive(Debug)]
+pub struct Token(*mut CachePadded<Node>);
+
+impl Node {
+    fn new(locked: bool) -> *mut CachePadded<Self> {
+        Box::into_raw(Box::new(CachePadded::new(Self {
+            locked: AtomicBool::new(locked),
+        })))
+    }
+}
+
+impl Default for ClhLock {
+    fn default() -> Self {
+        Self {
+            tail: AtomicPtr::new(Node::new(false)),
+        }
+    }
+}
+
+impl Drop for ClhLock {
+    fn drop(&mut self) {
+        // SAFETY: the tail node is only freed by its successor, and there is none.
+        drop(unsafe { Box::from_raw(*self.tail.get_mut()) });
+    }
+}
+
+impl RawLock for ClhLock {
+    type Token = Token;
+
+    fn lock(&self) -> Self::Token {
+        let node = Node::new(true);
+        let prev = self.tail.swap(node, Ordering::AcqRel);
+
+        let backoff = Backoff::new();
+        // SAFETY: `prev` is valid, as only its successor (us) frees it.
+        while unsafe { &*prev }.locked.load(Ordering::Acquire) {
+            backoff.snooze();
+        }
+
+        // SAFETY: the predecessor released the lock, so nobody else refers to `prev`.
+        drop(unsafe { Box::from_raw(prev) });
+        Token(node)
+    }
+
+    unsafe fn unlock(&self, token: Self::Token) {
+        (&*token.0).locked.store(false, Ordering::Release);
+    }
+}
+
+#[derive(Debug)]
+struct AbortableNode {
+    /// Null while the owner holds or waits for the lock, `AVAILABLE` once the owner released the
+    /// lock, and the owner's predecessor once the owner abandoned its place in the queue.
+    pred: AtomicPtr<CachePadded<AbortableNode>>,
+}
+
+static AVAILABLE: CachePadded<AbortableNode> = CachePadded::new(AbortableNode {
+    pred: AtomicPtr::new(ptr::null_mut()),
+});
+
+fn available() -> *mut CachePadded<AbortableNode> {
+    &AVAILABLE as *const _ as *mut _
+}
+
+/// A CLH lock whose waiters can abandon their place in the queue after a timeout.
+///
+/// See Scott, "Non-Blocking Timeout in Scalable Queue-Based Spin Locks", PODC 2002.
+#[derive(Debug)]
+pub struct AbortableClhLock {
+    tail: AtomicPtr<CachePadded<AbortableNode>>,
+}
+
+/// An abortable CLH lock token, owning the acquirer's queue node.
+#[derive(Debug)]
+pub struct AbortableToken(*mut CachePadded<AbortableNode>);
+
+impl AbortableNode {
+    fn new() -> *mut CachePadded<Self> {
+        Box::into_raw(Box::new(CachePadded::new(Self {
+            pred: AtomicPtr::new(ptr::null_mut()),
+        })))
+    }
+}
+
+impl Default for AbortableClhLock {
+    fn default() -> Self {
+        Self {
+            tail: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl AbortableClhLock {
+    fn acquire(&self, deadline: Option<Instant>) -> Result<AbortableToken, ()> {
+        let node = AbortableNode::new();
+        let mut pred = self.tail.swap(node, Ordering::AcqRel);
+
+        if pred.is_null() {
+            return Ok(AbortableToken(node));
+        }
+
+        let backoff = Backoff::new();
+        loop {
+            // SAFETY: `pred` is valid, as each node is freed only by its successor (us) or by its
+            // owner when it has no successor.
+            let pred_pred = unsafe { &*pred }.pred.load(Ordering::Acquire);
+
+            if pred_pred == available() {
+                // SAFETY: the predecessor released the lock, so nobody else refers to `pred`.
+                drop(unsafe { Box::from_raw(pred) });
+                return Ok(AbortableToken(node));
+            }
+
+            if !pred_pred.is_null() {
+                // The predecessor abandoned its place in the queue, so skip over it.
+                //
+                // SAFETY: the predecessor is gone, so nobody else refers to `pred`.
+                drop(unsafe { Box::from_raw(pred) });
+                pred = pred_pred;
+                continue;
+            }
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break;
+            }
+
+            backoff.snooze();
+        }
+
+        // Abandon our place in the queue.
+        if self
+            .tail
+            .compare_exchange(node, pred, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+        {
+            // SAFETY: nobody enqueued after us, so nobody refers to `node`.
+            drop(unsafe { Box::from_raw(node) });
+        } else {
+            // SAFETY: `node` is valid, as it is freed only by our successor after this store.
+            unsafe { &*node }.pred.store(pred, Ordering::Release);
+        }
+
+        Err(())
+    }
+}
+
+impl Drop for AbortableClhLock {
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        if !tail.is_null() {
+            // SAFETY: the tail node is released and has no successor, so nobody refers to it.
+            drop(unsafe { Box::from_raw(tail) });
+        }
+    }
+}
+
+impl RawLock for AbortableClhLock {
+    type Token = AbortableToken;
+
+    fn lock(&self) -> Self::Token {
+        self.acquire(None).unwrap()
+    }
+
+    unsafe fn unlock(&self, token: Self::Token) {
+        let node = token.0;
+
+        if self
+            .tail
+            .compare_exchange(node, ptr::null_mut(), Ordering::Release, Ordering::Relaxed)
+            .is_ok()
+        {
+            // SAFETY: nobody enqueued after us, so nobody refers to `node`.
+            drop(Box::from_raw(node));
+            return;
+        }
+
+        (&*node).pred.store(available(), Ordering::Release);
+    }
+}
+
+impl RawTryLock for AbortableClhLock {
+    fn try_lock(&self) -> Result<Self::Token, ()> {
+        self.try_lock_for(Duration::ZERO)
+    }
+}
+
+impl RawTimedLock for AbortableClhLock {
+    fn try_lock_for(&self, timeout: Duration) -> Result<Self::Token, ()> {
+        self.acquire(Some(Instant::now() + timeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+    use std::thread::scope;
+
+    use super::super::api;
+    use super::clhlock::{AbortableClhLock, ClhLock};
+    use crate::lock::Lock;
+
+    #[test]
+    fn smoke() {
+        api::tests::smoke::<ClhLock>();
+    }
+
+    #[test]
+    fn smoke_abortable() {
+        api::tests::smoke::<AbortableClhLock>();
+    }
+
+    #[test]
+    fn try_lock_for() {
+        let lock = Lock::<AbortableClhLock, usize>::new(0);
+        let guard = lock.lock();
+
+        scope(|s| {
+            // This waiter gives up on the held lock ...
+            let timed_out = s.spawn(|| lock.try_lock_for(Duration::from_millis(10)).is_err());
+            assert!(timed_out.join().unwrap());
+
+            // ... without keeping the following waiter from acquiring it.
+            let waiter = s.spawn(|| *lock.try_lock_for(Duration::from_secs(10)).unwrap() += 1);
+            drop(guard);
+            waiter.join().unwrap();
+        });
+
+        assert_eq!(lock.into_inner(), 1);
+    }
+
+    #[test]
+    fn abort_concurrent() {
+        const THREADS: usize = 16;
+        const STEPS: usize = 1024;
+
+        let lock = Lock::<AbortableClhLock, usize>::new(0);
+
+        let acquired = scope(|s| {
+            let handles = (0..THREADS)
+                .map(|i| {
+                    let lock = &lock;
+                    s.spawn(move || {
+                        let mut acquired = 0;
+                        for j in 0..STEPS {
+                            let timeout = Duration::from_micros(((i + j) % 4) as u64);
+                            if let Ok(mut guard) = lock.try_lock_for(timeout) {
+                                *guard += 1;
+                                acquired += 1;
+                            }
+                        }
+                        acquired
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .sum::<usize>()
+        });
+
+        assert_eq!(lock.into_inner(), acquired);
+    }
+}
@@ -0,0 +1,251 @@
+use core::cell::{Cell, UnsafeCell};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crossbeam_utils::CachePadded;
+
+use crate::lock::*;
+
+thread_local! {
+    static GROUP: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Sets the cohort group of the current thread, with which it acquires `Cohort` locks through the
+/// `RawLock` interface. Threads are in group 0 by default.
+pub fn set_cohort_group(group: usize) {
+    GROUP.with(|g| g.set(group));
+}
+
+/// Returns the cohort group of the current thread.
+pub fn cohort_group() -> usize {
+    GROUP.with(Cell::get)
+}
+
+struct Group<G: RawLock, L: RawLock> {
+    local: L,
+    /// The number of threads that are acquiring `local`.
+    waiters: AtomicUsize,
+    /// The global lock's token if the group owns the global lock, and the number of times the
+    /// ownership has been passed within the group since. Only accessed while holding `local`.
+    global: UnsafeCell<(Option<G::Token>, usize)>,
+}
+
+/// A cohort lock, composing a global lock with per-group local locks.
+///
+/// Threads acquire the local lock of their group first, and then the global lock unless the group
+/// already owns it. A releasing thread passes the ownership of the global lock to the next thread
+/// of its group if there is one, up to `bound` times in a row, so that the lock-protected data
+/// stays in the group's cache, e.g. of a socket.
+///
+/// The global lock should support being released by a thread other than its acquirer.
+///
+/// See Dice et al., "Lock Cohorting: A General Technique for Designing NUMA Locks", PPoPP 2012.
+pub struct Cohort<G: RawLock, L: RawLock> {
+    global: G,
+    groups: Box<[CachePadded<Group<G, L>>]>,
+    bound: usize,
+}
+
+/// A cohort lock token.
+pub struct CohortToken<L: RawLock> {
+    group: usize,
+    local: L::Token,
+}
+
+// The global lock's token is released by whichever thread of the group unlocks last.
+unsafe impl<G: RawLock + Send + Sync, L: RawLock + Send + Sync> Send for Cohort<G, L> where
+    G::Token: Send
+{
+}
+unsafe impl<G: RawLock + Send + Sync, L: RawLock + Send + Sync> Sync for Cohort<G, L> where
+    G::Token: Send
+{
+}
+
+impl<G: RawLock, L: RawLock> Cohort<G, L> {
+    /// The number of groups of a default cohort lock.
+    pub const DEFAULT_GROUPS: usize = 8;
+
+    /// The number of times in a row the ownership is passed within a group by default.
+    pub const DEFAULT_BOUND: usize = 64;
+
+    /// Creates a new cohort lock with the given number of groups, where the ownership is passed
+    /// within a group at most `bound` times in a row.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `groups` is 0.
+    pub fn new(groups: usize, bound: usize) -> Self {
+        assert!(groups > 0, "a cohort lock should have at least one group");
+
+        Self {
+            global: G::default(),
+            groups: (0..groups)
+                .map(|_| {
+                    CachePadded::new(Group {
+                        local: L::default(),
+                        waiters: AtomicUsize::new(0),
+                        global: UnsafeCell::new((None, 0)),
+                    })
+                })
+                .collect(),
+            bound,
+        }
+    }
+
+    /// Returns the number of groups.
+    pub fn groups(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// Acquires the lock as a member of the given group. Groups beyond the number of groups wrap
+    /// around.
+    pub fn lock_in(&self, group: usize) -> CohortToken<L> {
+        let group = group % self.groups.len();
+        let g = &self.groups[group];
+
+        let _ = g.waiters.fetch_add(1, Ordering::Relaxed);
+        let local = g.local.lock();
+        let _ = g.waiters.fetch_sub(1, Ordering::Relaxed);
+
+        // SAFETY: we hold `local`, so we have unique access to `global`.
+        let global = unsafe { &mut *g.global.get() };
+        if global.0.is_none() {
+            *global = (Some(self.global.lock()), 0);
+        }
+
+        CohortToken { group, local }
+    }
+
+    /// Releases the lock.
+    ///
+    /// # Safety
+    ///
+    /// `token` should be given by the corresponding `lock_in()`.
+    pub unsafe fn unlock_in(&self, token: CohortToken<L>) {
+        let g = &self.groups[token.group];
+
+        // SAFETY: we hold `local`, so we have unique access to `global`.
+        let global = &mut *g.global.get();
+        if global.1 < self.bound && g.waiters.load(Ordering::Relaxed) != 0 {
+            // A thread of the group will acquire `local` next, and inherit the global lock.
+            global.1 += 1;
+        } else {
+            self.global.unlock(global.0.take().unwrap());
+        }
+
+        g.local.unlock(token.local);
+    }
+}
+
+impl<G: RawLock, L: RawLock> Default for Cohort<G, L> {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_GROUPS, Self::DEFAULT_BOUND)
+    }
+}
+
+impl<G: RawLock, L: RawLock> fmt::Debug for Cohort<G, L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cohort")
+            .field("groups", &self.groups.len())
+            .field("bound", &self.bound)
+            .finish()
+    }
+}
+
+impl<L: RawLock> fmt::Debug for CohortToken<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CohortToken")
+            .field("group", &self.group)
+            .finish()
+    }
+}
+
+/// Acquires the cohort lock as a member of the current thread's group, which is set by
+/// `set_cohort_group()`.
+impl<G: RawLock, L: RawLock> RawLock for Cohort<G, L>
+where
+    G::Token: Send,
+{
+    type Token = CohortToken<L>;
+
+    fn lock(&self) -> Self::Token {
+        self.lock_in(cohort_group())
+    }
+
+    unsafe fn unlock(&self, token: Self::Token) {
+        self.unlock_in(token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::hint;
+    use core::sync::atomic::Ordering;
+    use std::thread::scope;
+
+    use super::super::api;
+    use super::cohort::{set_cohort_group, Cohort};
+    use crate::lock::{Lock, McsLock, RawTryLock, SpinLock, TicketLock};
+
+    #[test]
+    fn smoke() {
+        api::tests::smoke::<Cohort<TicketLock, McsLock>>();
+        api::tests::smoke::<Cohort<SpinLock, TicketLock>>();
+    }
+
+    #[test]
+    fn groups() {
+        const THREADS: usize = 8;
+        const STEPS: usize = 4096;
+
+        let lock = Lock::<Cohort<TicketLock, McsLock>, usize>::new(0);
+
+        scope(|s| {
+            for i in 0..THREADS {
+                let lock = &lock;
+                s.spawn(move || {
+                    set_cohort_group(i % 3);
+                    for _ in 0..STEPS {
+                        *lock.lock() += 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(lock.into_inner(), THREADS * STEPS);
+    }
+
+    /// Releases the lock while a thread of the same group waits for it, and returns the number of
+    /// passes the waiter observes.
+    fn pass(bound: usize) -> usize {
+        let lock = Cohort::<TicketLock, SpinLock>::new(2, bound);
+
+        let token = lock.lock_in(0);
+        let passes = scope(|s| {
+            let waiter = s.spawn(|| {
+                let token = lock.lock_in(2);
+                // SAFETY: we hold the local lock of group 0.
+                let passes = unsafe { &*lock.groups[0].global.get() }.1;
+                unsafe { lock.unlock_in(token) };
+                passes
+            });
+
+            while lock.groups[0].waiters.load(Ordering::Relaxed) == 0 {
+                hint::spin_loop();
+            }
+            unsafe { lock.unlock_in(token) };
+            waiter.join().unwrap()
+        });
+
+        // The global lock is released once no thread of the group waits.
+        assert!(lock.global.try_lock().is_ok());
+        passes
+    }
+
+    #[test]
+    fn bound() {
+        assert_eq!(pass(0), 0);
+        assert_eq!(pass(1), 1);
+    }
+}
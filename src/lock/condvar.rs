@@ -0,0 +1,165 @@
+use core::time::Duration;
+use std::sync::{self, MutexGuard, PoisonError, WaitTimeoutResult};
+
+use crate::lock::*;
+
+/// A condition variable that works with `Lock<L, T>` for any raw lock `L`.
+///
+/// Like `std::sync::Condvar`, waiting may wake up spuriously.
+#[derive(Debug, Default)]
+pub struct Condvar {
+    // Waiters hold `inner` from before releasing their lock until they park on `cond`, and
+    // notifiers take `inner` before notifying. Hence no notification is lost in between.
+    inner: sync::Mutex<()>,
+    cond: sync::Condvar,
+}
+
+impl Condvar {
+    /// Creates a new condition variable.
+    pub const fn new() -> Self {
+        Self {
+            inner: sync::Mutex::new(()),
+            cond: sync::Condvar::new(),
+        }
+    }
+
+    fn inner(&self) -> MutexGuard<'_, ()> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Releases the guard's lock, blocks until notified, and reacquires the lock.
+    #[track_caller]
+    pub fn wait<L: RawLock, T>(&self, guard: &mut LockGuard<'_, L, T>) {
+        let inner = self.inner();
+        guard.unlocked(|| {
+            let _inner = self
+                .cond
+                .wait(inner)
+                .unwrap_or_else(PoisonError::into_inner);
+        });
+    }
+
+    /// Waits on the condition variable while `condition` holds for the lock-protected value.
+    #[track_caller]
+    pub fn wait_while<L: RawLock, T, F>(&self, guard: &mut LockGuard<'_, L, T>, mut condition: F)
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(guard) {
+            self.wait(guard);
+        }
+    }
+
+    /// Releases the guard's lock, blocks until notified or until the timeout expires, and
+    /// reacquires the lock.
+    #[track_caller]
+    pub fn wait_timeout<L: RawLock, T>(
+        &self,
+        guard: &mut LockGuard<'_, L, T>,
+        timeout: Duration,
+    ) -> WaitTimeoutResult {
+        let inner = self.inner();
+        guard.unlocked(|| {
+            let (_inner, result) = self
+                .cond
+                .wait_timeout(inner, timeout)
+                .unwrap_or_else(PoisonError::into_inner);
+            result
+        })
+    }
+
+    /// Wakes up one thread waiting on the condition variable.
+    pub fn notify_one(&self) {
+        let _inner = self.inner();
+        self.cond.notify_one();
+    }
+
+    /// Wakes up all threads waiting on the condition variable.
+    pub fn notify_all(&self) {
+        let _inner = self.inner();
+        self.cond.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+    use std::collections::VecDeque;
+    use std::thread::scope;
+
+    use super::Condvar;
+    use crate::lock::{Lock, McsLock, RawLock, SpinLock};
+
+    fn producer_consumer<L: RawLock>() {
+        const THREADS: usize = 4;
+        const STEPS: usize = 1024;
+
+        let queue = Lock::<L, VecDeque<usize>>::new(VecDeque::new());
+        let cond = Condvar::new();
+
+        let sum = scope(|s| {
+            let consumers = (0..THREADS)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut sum = 0;
+                        for _ in 0..STEPS {
+                            let mut guard = queue.lock();
+                            cond.wait_while(&mut guard, |q| q.is_empty());
+                            sum += guard.pop_front().unwrap();
+                        }
+                        sum
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for i in 0..STEPS {
+                        queue.lock().push_back(i);
+                        cond.notify_one();
+                    }
+                });
+            }
+
+            consumers
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .sum::<usize>()
+        });
+
+        assert_eq!(sum, THREADS * (0..STEPS).sum::<usize>());
+    }
+
+    #[test]
+    fn producer_consumer_spinlock() {
+        producer_consumer::<SpinLock>();
+    }
+
+    #[test]
+    fn producer_consumer_mcslock() {
+        producer_consumer::<McsLock>();
+    }
+
+    #[test]
+    fn wait_timeout() {
+        let lock = Lock::<SpinLock, bool>::new(false);
+        let cond = Condvar::new();
+
+        let mut guard = lock.lock();
+        assert!(cond
+            .wait_timeout(&mut guard, Duration::from_millis(10))
+            .timed_out());
+        assert!(!*guard);
+
+        scope(|s| {
+            s.spawn(|| {
+                *lock.lock() = true;
+                cond.notify_all();
+            });
+
+            while !*guard {
+                let _ = cond.wait_timeout(&mut guard, Duration::from_secs(10));
+            }
+        });
+    }
+}
@@ -0,0 +1,255 @@
+use core::fmt;
+use core::sync::atomic::{fence, AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crossbeam_epoch::Guard;
+use crossbeam_utils::Backoff;
+
+use super::flatcombining::{Executor, Op, Slots};
+use crate::adt::{ConcurrentMap, SequentialMap};
+
+struct Shared<M> {
+    slots: Slots<M>,
+    /// Whether the server is parked or about to park, and should be unparked on publishing.
+    sleeping: AtomicBool,
+    stop: AtomicBool,
+}
+
+/// A concurrent map whose operations are delegated to a server thread that owns a sequential map.
+///
+/// Clients publish their operations to per-client slots and wait, while the server sweeps the
+/// slots and applies the published operations. The map stays in the server's cache, and clients
+/// never contend on a lock. The server parks when idle.
+///
+/// See Roghanchi et al., "ffwd: delegation is (much) faster than you think", SOSP 2017.
+pub struct Delegation<M: Send + 'static> {
+    shared: Arc<Shared<M>>,
+    server: Option<JoinHandle<M>>,
+}
+
+impl<M: Send + 'static> Delegation<M> {
+    /// The number of slots by default.
+    pub const DEFAULT_SLOTS: usize = 64;
+
+    /// Creates a new delegation map, spawning its server thread.
+    pub fn new(map: M) -> Self {
+        Self::with_slots(map, Self::DEFAULT_SLOTS)
+    }
+
+    /// Creates a new delegation map with the given number of slots, spawning its server thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `slots` is 0.
+    pub fn with_slots(mut map: M, slots: usize) -> Self {
+        let shared = Arc::new(Shared {
+            slots: Slots::new(slots),
+            sleeping: AtomicBool::new(false),
+            stop: AtomicBool::new(false),
+        });
+
+        let server = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let backoff = Backoff::new();
+                while !shared.stop.load(Ordering::Acquire) {
+                    if shared.slots.combine(&mut map, None) {
+                        backoff.reset();
+                        continue;
+                    }
+
+                    if !backoff.is_completed() {
+                        backoff.snooze();
+                        continue;
+                    }
+
+                    // Clients check `sleeping` after publishing, so an operation is either
+                    // applied by the following sweep or unparks us.
+                    shared.sleeping.store(true, Ordering::Relaxed);
+                    fence(Ordering::SeqCst);
+                    let applied = shared.slots.combine(&mut map, None);
+                    if !applied && !shared.stop.load(Ordering::Acquire) {
+                        thread::park();
+                    }
+                    shared.sleeping.store(false, Ordering::Relaxed);
+                    backoff.reset();
+                }
+                map
+            })
+        };
+
+        Self {
+            shared,
+            server: Some(server),
+        }
+    }
+
+    /// Stops the server thread and retrieves the sequential map.
+    pub fn into_inner(mut self) -> M {
+        self.stop().expect("the server thread panicked")
+    }
+
+    /// Stops the server thread and retrieves the sequential map, or `None` if the server thread
+    /// panicked.
+    fn stop(&mut self) -> Option<M> {
+        let server = self.server.take()?;
+        self.shared.stop.store(true, Ordering::Release);
+        server.thread().unpark();
+        server.join().ok()
+    }
+
+    /// Waits a little, so that the server makes progress.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server thread is dead, in which case the operations are never applied.
+    fn snooze(&self, backoff: &Backoff) {
+        assert!(
+            !self.server.as_ref().unwrap().is_finished(),
+            "the server thread panicked"
+        );
+        backoff.snooze();
+    }
+}
+
+impl<M: Send + 'static> Executor<M> for Delegation<M> {
+    unsafe fn run<R>(&self, op: Op<M>, finish: impl FnOnce() -> R) -> R {
+        let backoff = Backoff::new();
+        let own = loop {
+            if let Ok(own) = self.shared.slots.try_claim() {
+                break own;
+            }
+
+            self.snooze(&backoff);
+        };
+
+        self.shared.slots.publish(own, op);
+        fence(Ordering::SeqCst);
+        if self.shared.sleeping.load(Ordering::Relaxed) {
+            self.server.as_ref().unwrap().thread().unpark();
+        }
+
+        let backoff = Backoff::new();
+        while !self.shared.slots.is_done(own) {
+            self.snooze(&backoff);
+        }
+
+        let _consume = self.shared.slots.consume(own);
+        finish()
+    }
+}
+
+impl<M: Send + 'static> Drop for Delegation<M> {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+impl<M: Default + Send + 'static> Default for Delegation<M> {
+    fn default() -> Self {
+        Self::new(M::default())
+    }
+}
+
+impl<M: Send + 'static> fmt::Debug for Delegation<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Delegation")
+            .field("slots", &self.shared.slots.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: ?Sized + Sync, V: Send + Sync, M> ConcurrentMap<K, V> for Delegation<M>
+where
+    M: SequentialMap<K, V> + Send + 'static,
+{
+    fn lookup<'a, F, R>(&'a self, key: &'a K, _guard: &'a Guard, f: F) -> R
+    where
+        F: FnOnce(Option<&V>) -> R,
+    {
+        self.run_lookup(key, f)
+    }
+
+    fn insert<'a>(&'a self, key: &'a K, value: V, _guard: &'a Guard) -> Result<(), V> {
+        self.run_insert(key, value)
+    }
+
+    fn delete(&self, key: &K, _guard: &Guard) -> Result<V, ()> {
+        self.run_delete(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread::{self, scope};
+    use std::time::Duration;
+
+    use crossbeam_epoch::pin;
+
+    use super::super::flatcombining::tests::Explosive;
+    use super::Delegation;
+    use crate::adt::ConcurrentMap;
+    use crate::test::adt::map;
+
+    #[test]
+    fn log_concurrent() {
+        const THREADS: usize = 16;
+        const STEPS: usize = 4096;
+        map::log_concurrent::<u8, Delegation<HashMap<u8, usize>>>(THREADS, STEPS);
+    }
+
+    #[test]
+    fn shared_slots() {
+        const THREADS: usize = 8;
+        const STEPS: usize = 1024;
+
+        let map = Delegation::with_slots(HashMap::new(), 3);
+
+        scope(|s| {
+            for i in 0..THREADS {
+                let map = &map;
+                s.spawn(move || {
+                    for step in 0..STEPS {
+                        let key = step * THREADS + i;
+                        assert_eq!(map.insert(&key, key, &pin()), Ok(()));
+                        map.lookup(&key, &pin(), |v| assert_eq!(v, Some(&key)));
+                        if step % 2 == 0 {
+                            assert_eq!(map.delete(&key, &pin()), Ok(key));
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(map.into_inner().len(), THREADS * STEPS / 2);
+    }
+
+    #[test]
+    fn idle() {
+        let map = Delegation::<HashMap<usize, usize>>::default();
+
+        for i in 0..16 {
+            // Let the server park.
+            thread::sleep(Duration::from_millis(1));
+            assert_eq!(map.insert(&i, i, &pin()), Ok(()));
+        }
+
+        drop(map);
+    }
+
+    #[test]
+    fn panic() {
+        let map = Delegation::<HashMap<Explosive, usize>>::default();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| map.insert(&Explosive(0), 0, &pin())));
+        assert!(result.is_err());
+
+        // The server survives the panic.
+        assert_eq!(map.insert(&Explosive(1), 1, &pin()), Ok(()));
+        map.lookup(&Explosive(1), &pin(), |v| assert_eq!(v, Some(&1)));
+        assert_eq!(map.into_inner().len(), 1);
+    }
+}
@@ -0,0 +1,429 @@
+use core::any::Any;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::panic::{self, AssertUnwindSafe};
+
+use crossbeam_epoch::Guard;
+use crossbeam_utils::{Backoff, CachePadded};
+
+use super::reentrantlock::current_thread_id;
+use crate::adt::{ConcurrentMap, SequentialMap};
+use crate::lock::*;
+
+const EMPTY: usize = 0;
+/// The publisher is writing an operation to the slot.
+const CLAIMED: usize = 1;
+const PENDING: usize = 2;
+/// The operation is applied, and the publisher has not consumed its result yet.
+const DONE: usize = 3;
+
+/// A published operation, pointing to a closure on the publisher's stack.
+pub(super) enum Op<M> {
+    /// An operation whose result borrows the map, so that the map should not be modified until
+    /// the result is consumed.
+    Read(*mut dyn FnMut(&M)),
+    Write(*mut dyn FnMut(&mut M)),
+}
+
+impl<M> Op<M> {
+    /// # Safety
+    ///
+    /// The operation should not be applied after `op` is dropped.
+    pub(super) unsafe fn read<'a>(op: &'a mut (dyn FnMut(&M) + 'a)) -> Self {
+        Op::Read(mem::transmute::<
+            *mut (dyn FnMut(&M) + 'a),
+            *mut (dyn FnMut(&M) + 'static),
+        >(op))
+    }
+
+    /// # Safety
+    ///
+    /// The operation should not be applied after `op` is dropped.
+    pub(super) unsafe fn write<'a>(op: &'a mut (dyn FnMut(&mut M) + 'a)) -> Self {
+        Op::Write(mem::transmute::<
+            *mut (dyn FnMut(&mut M) + 'a),
+            *mut (dyn FnMut(&mut M) + 'static),
+        >(op))
+    }
+
+    /// # Safety
+    ///
+    /// The closure of the operation should be valid.
+    pub(super) unsafe fn apply(&self, map: &mut M) {
+        match self {
+            Op::Read(op) => (**op)(map),
+            Op::Write(op) => (**op)(map),
+        }
+    }
+}
+
+struct Slot<M> {
+    state: AtomicUsize,
+    op: UnsafeCell<Option<Op<M>>>,
+    /// The payload of the panic of the done operation, if it panicked.
+    panic: UnsafeCell<Option<Box<dyn Any + Send>>>,
+}
+
+/// Consumes the result of the slot's operation on drop, even if the publisher panics.
+pub(super) struct Consume<'s>(&'s AtomicUsize);
+
+impl<'s> Drop for Consume<'s> {
+    fn drop(&mut self) {
+        self.0.store(EMPTY, Ordering::Release);
+    }
+}
+
+/// Per-thread slots, to which threads publish operations on a map for another thread to apply.
+pub(super) struct Slots<M> {
+    slots: Box<[CachePadded<Slot<M>>]>,
+}
+
+unsafe impl<M: Send> Send for Slots<M> {}
+unsafe impl<M: Send> Sync for Slots<M> {}
+
+impl<M> Slots<M> {
+    /// # Panics
+    ///
+    /// Panics if `slots` is 0.
+    pub(super) fn new(slots: usize) -> Self {
+        assert!(slots > 0, "there should be at least one slot");
+
+        Self {
+            slots: (0..slots)
+                .map(|_| {
+                    CachePadded::new(Slot {
+                        state: AtomicUsize::new(EMPTY),
+                        op: UnsafeCell::new(None),
+                        panic: UnsafeCell::new(None),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Tries to claim the current thread's slot, which may be shared with other threads. Returns
+    /// the slot's index.
+    pub(super) fn try_claim(&self) -> Result<usize, ()> {
+        let index = current_thread_id() % self.slots.len();
+        self.slots[index]
+            .state
+            .compare_exchange(EMPTY, CLAIMED, Ordering::Acquire, Ordering::Relaxed)
+            .map(|_| index)
+            .map_err(|_| ())
+    }
+
+    /// Publishes the operation to the claimed slot.
+    ///
+    /// # Safety
+    ///
+    /// The slot should be claimed by the current thread, and the closure of the operation should
+    /// be valid until the operation is done.
+    pub(super) unsafe fn publish(&self, index: usize, op: Op<M>) {
+        let slot = &self.slots[index];
+        *slot.op.get() = Some(op);
+        slot.state.store(PENDING, Ordering::Release);
+    }
+
+    /// Returns whether the operation of the slot is done.
+    pub(super) fn is_done(&self, index: usize) -> bool {
+        self.slots[index].state.load(Ordering::Acquire) == DONE
+    }
+
+    /// Returns a guard that consumes the result of the done operation of the slot on drop, after
+    /// which the map may be modified and the slot may be claimed again.
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of the operation if it panicked, after consuming it.
+    pub(super) fn consume(&self, index: usize) -> Consume<'_> {
+        let slot = &self.slots[index];
+        let consume = Consume(&slot.state);
+
+        // SAFETY: the operation is done, so the combiner does not access the slot anymore.
+        if let Some(payload) = unsafe { (*slot.panic.get()).take() } {
+            panic::resume_unwind(payload);
+        }
+        consume
+    }
+
+    /// Applies the pending operations: writes first, and then reads. Returns once the results of
+    /// the operations are consumed, except for the slot `own`. Returns whether any operation was
+    /// applied.
+    pub(super) fn combine(&self, map: &mut M, own: Option<usize>) -> bool {
+        let mut applied = false;
+
+        for slot in self.slots.iter() {
+            if slot.state.load(Ordering::Acquire) != PENDING {
+                continue;
+            }
+
+            // SAFETY: the publisher waits until the operation is done, so its closure is valid.
+            if let Some(op @ Op::Write(_)) = unsafe { &*slot.op.get() } {
+                Self::apply(slot, op, map);
+                applied = true;
+            }
+        }
+
+        for slot in self.slots.iter() {
+            if slot.state.load(Ordering::Acquire) != PENDING {
+                continue;
+            }
+
+            // SAFETY: the publisher waits until the operation is done, so its closure is valid.
+            if let Some(op @ Op::Read(_)) = unsafe { &*slot.op.get() } {
+                Self::apply(slot, op, map);
+                applied = true;
+            }
+        }
+
+        // Only the combiner marks slots done, so the publishers eventually consume them.
+        let backoff = Backoff::new();
+        for (i, slot) in self.slots.iter().enumerate() {
+            if Some(i) == own {
+                continue;
+            }
+
+            while slot.state.load(Ordering::Acquire) == DONE {
+                backoff.snooze();
+            }
+        }
+
+        applied
+    }
+
+    /// Applies the pending operation of the slot and marks it done. If the operation panics, the
+    /// panic is passed to the publisher.
+    fn apply(slot: &Slot<M>, op: &Op<M>, map: &mut M) {
+        // SAFETY: the publisher waits until the operation is done, so its closure is valid.
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| unsafe { op.apply(map) })) {
+            // SAFETY: the publisher does not access the slot until it is done.
+            unsafe { *slot.panic.get() = Some(payload) };
+        }
+        slot.state.store(DONE, Ordering::Release);
+    }
+}
+
+/// Applies operations on a map on behalf of the current thread.
+pub(super) trait Executor<M> {
+    /// Applies the operation, and calls `finish` before the map is modified by other operations.
+    ///
+    /// # Safety
+    ///
+    /// The closure of the operation should be valid until this function returns.
+    unsafe fn run<R>(&self, op: Op<M>, finish: impl FnOnce() -> R) -> R;
+
+    fn run_lookup<K: ?Sized, V, F, R>(&self, key: &K, f: F) -> R
+    where
+        M: SequentialMap<K, V>,
+        F: FnOnce(Option<&V>) -> R,
+    {
+        let mut value = None;
+        let mut op = |map: &M| value = Some(map.lookup(key).map(|v| v as *const V));
+
+        // SAFETY: `op` outlives `run()`, and the map is not modified until `f` returns.
+        unsafe { self.run(Op::read(&mut op), || f(value.take().unwrap().map(|v| &*v))) }
+    }
+
+    fn run_insert<K: ?Sized, V>(&self, key: &K, value: V) -> Result<(), V>
+    where
+        M: SequentialMap<K, V>,
+    {
+        let mut value = Some(value);
+        let mut result = None;
+        let mut op = |map: &mut M| result = Some(map.insert(key, value.take().unwrap()));
+
+        // SAFETY: `op` outlives `run()`.
+        unsafe { self.run(Op::write(&mut op), || result.take().unwrap()) }
+    }
+
+    fn run_delete<K: ?Sized, V>(&self, key: &K) -> Result<V, ()>
+    where
+        M: SequentialMap<K, V>,
+    {
+        let mut result = None;
+        let mut op = |map: &mut M| result = Some(map.delete(key));
+
+        // SAFETY: `op` outlives `run()`.
+        unsafe { self.run(Op::write(&mut op), || result.take().unwrap()) }
+    }
+}
+
+/// A concurrent map that applies the operations of many threads to a sequential map in batches.
+///
+/// Threads publish their operations to per-thread slots and wait. Whoever acquires the lock
+/// becomes the combiner: it applies all published operations while the lock stays in its cache,
+/// instead of handing the lock and the map over to each thread in turn. Threads sharing a slot
+/// fall back to acquiring the lock themselves.
+///
+/// See Hendler et al., "Flat Combining and the Synchronization-Parallelism Tradeoff", SPAA 2010.
+pub struct FlatCombining<M> {
+    map: Lock<SpinLock, M>,
+    slots: Slots<M>,
+}
+
+impl<M> FlatCombining<M> {
+    /// The number of slots by default.
+    pub const DEFAULT_SLOTS: usize = 64;
+
+    /// Creates a new flat combining map.
+    pub fn new(map: M) -> Self {
+        Self::with_slots(map, Self::DEFAULT_SLOTS)
+    }
+
+    /// Creates a new flat combining map with the given number of slots.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `slots` is 0.
+    pub fn with_slots(map: M, slots: usize) -> Self {
+        Self {
+            map: Lock::new(map),
+            slots: Slots::new(slots),
+        }
+    }
+
+    /// Destroys the flat combining map and retrieves the sequential map.
+    pub fn into_inner(self) -> M {
+        self.map.into_inner()
+    }
+}
+
+impl<M> Executor<M> for FlatCombining<M> {
+    unsafe fn run<R>(&self, op: Op<M>, finish: impl FnOnce() -> R) -> R {
+        let Ok(own) = self.slots.try_claim() else {
+            // Another thread is using the slot, so apply the operation with the lock held.
+            let mut map = self.map.lock();
+            let _ = self.slots.combine(&mut map, None);
+            op.apply(&mut map);
+            return finish();
+        };
+
+        self.slots.publish(own, op);
+
+        let backoff = Backoff::new();
+        loop {
+            if self.slots.is_done(own) {
+                let _consume = self.slots.consume(own);
+                return finish();
+            }
+
+            if let Ok(mut map) = self.map.try_lock() {
+                // Our operation was published before, so it is done after combining.
+                let _ = self.slots.combine(&mut map, Some(own));
+                let _consume = self.slots.consume(own);
+                return finish();
+            }
+
+            backoff.snooze();
+        }
+    }
+}
+
+impl<M: Default> Default for FlatCombining<M> {
+    fn default() -> Self {
+        Self::new(M::default())
+    }
+}
+
+impl<M> fmt::Debug for FlatCombining<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FlatCombining")
+            .field("slots", &self.slots.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: ?Sized + Sync, V: Send + Sync, M> ConcurrentMap<K, V> for FlatCombining<M>
+where
+    M: SequentialMap<K, V> + Send,
+{
+    fn lookup<'a, F, R>(&'a self, key: &'a K, _guard: &'a Guard, f: F) -> R
+    where
+        F: FnOnce(Option<&V>) -> R,
+    {
+        self.run_lookup(key, f)
+    }
+
+    fn insert<'a>(&'a self, key: &'a K, value: V, _guard: &'a Guard) -> Result<(), V> {
+        self.run_insert(key, value)
+    }
+
+    fn delete(&self, key: &K, _guard: &Guard) -> Result<V, ()> {
+        self.run_delete(key)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use std::hash::{Hash, Hasher};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread::scope;
+
+    use crossbeam_epoch::pin;
+
+    use super::FlatCombining;
+    use crate::adt::ConcurrentMap;
+    use crate::test::adt::map;
+
+    /// A key whose hashing panics if it is 0.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) struct Explosive(pub(crate) usize);
+
+    impl Hash for Explosive {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            assert_ne!(self.0, 0, "boom");
+            self.0.hash(state);
+        }
+    }
+
+    #[test]
+    fn log_concurrent() {
+        const THREADS: usize = 16;
+        const STEPS: usize = 4096;
+        map::log_concurrent::<u8, FlatCombining<HashMap<u8, usize>>>(THREADS, STEPS);
+    }
+
+    #[test]
+    fn shared_slots() {
+        const THREADS: usize = 8;
+        const STEPS: usize = 1024;
+
+        let map = FlatCombining::with_slots(HashMap::new(), 3);
+
+        scope(|s| {
+            for i in 0..THREADS {
+                let map = &map;
+                s.spawn(move || {
+                    for step in 0..STEPS {
+                        let key = step * THREADS + i;
+                        assert_eq!(map.insert(&key, key, &pin()), Ok(()));
+                        map.lookup(&key, &pin(), |v| assert_eq!(v, Some(&key)));
+                        if step % 2 == 0 {
+                            assert_eq!(map.delete(&key, &pin()), Ok(key));
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(map.into_inner().len(), THREADS * STEPS / 2);
+    }
+
+    #[test]
+    fn panic() {
+        let map = FlatCombining::<HashMap<Explosive, usize>>::default();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| map.insert(&Explosive(0), 0, &pin())));
+        assert!(result.is_err());
+
+        assert_eq!(map.insert(&Explosive(1), 1, &pin()), Ok(()));
+        map.lookup(&Explosive(1), &pin(), |v| assert_eq!(v, Some(&1)));
+    }
+}
@@ -0,0 +1,129 @@
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crossbeam_utils::Backoff;
+
+use crate::lock::*;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+const CONTENDED: u32 = 2;
+
+/// A futex-based lock that spins for a while and then parks in the kernel.
+///
+/// See Drepper, "Futexes Are Tricky", 2011.
+#[derive(Debug)]
+pub struct FutexLock {
+    /// `UNLOCKED`, `LOCKED` without waiters, or `CONTENDED` if there may be parked waiters.
+    state: AtomicU32,
+}
+
+impl Default for FutexLock {
+    fn default() -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+        }
+    }
+}
+
+impl FutexLock {
+    /// Parks the current thread while the state is `expected`.
+    fn wait(&self, expected: u32) {
+        // SAFETY: `state` is a valid, aligned 32-bit integer. Spurious wake-ups are handled by the
+        // caller.
+        let _ = unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                self.state.as_ptr(),
+                libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+                expected,
+                ptr::null::<libc::timespec>(),
+            )
+        };
+    }
+
+    /// Wakes up one thread parked on the state.
+    fn wake_one(&self) {
+        // SAFETY: `state` is a valid, aligned 32-bit integer.
+        let _ = unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                self.state.as_ptr(),
+                libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                1,
+            )
+        };
+    }
+}
+
+impl RawLock for FutexLock {
+    type Token = ();
+
+    fn lock(&self) {
+        if self.try_lock().is_ok() {
+            return;
+        }
+
+        let backoff = Backoff::new();
+        while !backoff.is_completed() {
+            if self.state.load(Ordering::Relaxed) == UNLOCKED && self.try_lock().is_ok() {
+                return;
+            }
+            backoff.snooze();
+        }
+
+        // From now on, we may be parked, so mark the lock as contended.
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            self.wait(CONTENDED);
+        }
+    }
+
+    unsafe fn unlock(&self, _token: ()) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            self.wake_one();
+        }
+    }
+}
+
+impl RawTryLock for FutexLock {
+    fn try_lock(&self) -> Result<(), ()> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .map(|_| ())
+            .map_err(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+    use std::thread::{scope, sleep};
+
+    use super::super::api;
+    use super::futexlock::FutexLock;
+    use crate::lock::Lock;
+
+    #[test]
+    fn smoke() {
+        api::tests::smoke::<FutexLock>();
+    }
+
+    #[test]
+    fn long_critical_section() {
+        const THREADS: usize = 8;
+
+        let lock = Lock::<FutexLock, usize>::new(0);
+
+        scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    let mut guard = lock.lock();
+                    sleep(Duration::from_millis(5));
+                    *guard += 1;
+                });
+            }
+        });
+
+        assert_eq!(lock.into_inner(), THREADS);
+    }
+}
@@ -0,0 +1,557 @@
+//! Lock-order checking in debug builds.
+//!
+//! Every `Lock` records the edges "lock A was held while acquiring lock B" of all threads into a
+//! global graph. A blocking acquisition that would close a cycle in the graph may deadlock with
+//! other threads, so it panics with the acquisition sites of the edges on the cycle. Failed and
+//! successful try-acquisitions do not block, so they do not add edges into the acquired lock.
+//!
+//! Acquisitions capture a backtrace, printed if enabled by `RUST_BACKTRACE` or
+//! `RUST_LIB_BACKTRACE`, once the lock is nested with another lock, i.e. has edges, or if they
+//! add edges. Until then, they only record their location, so that unnested locks stay cheap. In
+//! particular, the first edge from a lock may only have the location of its held side.
+//!
+//! Only `Lock` and the types built on it, e.g. `PoisoningLock`, `Condvar` and lock sets, are
+//! checked. Other locks such as `RwLock`, `ReentrantLock` and `StripedLocks`, and raw locks used
+//! directly, are not.
+//!
+//! A lock is considered held by the thread that acquired it until it is released, even if its
+//! guard is sent to another thread in the meantime. For the same reason, acquiring a lock again by
+//! the thread holding it is not reported. In release builds, nothing is checked.
+
+use core::fmt;
+use core::panic::Location;
+#[cfg(debug_assertions)]
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(debug_assertions)]
+use std::backtrace::Backtrace;
+#[cfg(debug_assertions)]
+use std::cell::RefCell;
+#[cfg(debug_assertions)]
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(debug_assertions)]
+use std::sync::{Arc, Mutex, PoisonError};
+
+#[cfg(debug_assertions)]
+use super::reentrantlock::current_thread_id;
+
+/// An acquisition of a lock.
+#[cfg(debug_assertions)]
+struct Acquisition {
+    location: &'static Location<'static>,
+    backtrace: Option<Backtrace>,
+}
+
+#[cfg(debug_assertions)]
+impl fmt::Display for Acquisition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.location)?;
+        if let Some(backtrace) = &self.backtrace {
+            write!(f, "\n{backtrace}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The edge "`held` was held while acquiring `acquired`".
+#[cfg(debug_assertions)]
+struct Edge {
+    held: Arc<Acquisition>,
+    acquired: Arc<Acquisition>,
+}
+
+/// A lock in the lock-order graph.
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct Node {
+    /// The edges from the lock, indexed by the locks acquired while holding it.
+    to: BTreeMap<usize, Edge>,
+    /// The locks held while acquiring the lock.
+    from: BTreeSet<usize>,
+}
+
+/// The lock-order graph, indexed by the identifiers of the locks.
+#[cfg(debug_assertions)]
+static GRAPH: Mutex<BTreeMap<usize, Node>> = Mutex::new(BTreeMap::new());
+
+/// The state of a lock, shared with the entries of the threads that acquired it in `HELD`.
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct State {
+    /// The identifier of the thread holding the lock, or 0 if the lock is not held.
+    holder: AtomicUsize,
+    /// Whether the graph has edges from or to the lock.
+    ordered: AtomicBool,
+}
+
+/// A lock acquired by the current thread.
+#[cfg(debug_assertions)]
+struct Held {
+    id: usize,
+    state: Arc<State>,
+    location: &'static Location<'static>,
+    /// The acquisition if it captured a backtrace.
+    acquisition: Option<Arc<Acquisition>>,
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    /// The locks acquired by the current thread, in the order of acquisition. A lock released by
+    /// another thread stays here until pruned.
+    static HELD: RefCell<Vec<Held>> = const { RefCell::new(Vec::new()) };
+
+    /// The edges in the graph known by the current thread, to acquire nested locks without
+    /// locking the graph again. Identifiers are not reused, so edges of dropped locks are harmless.
+    static KNOWN: RefCell<BTreeSet<(usize, usize)>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+/// The number of edges in `KNOWN` above which it is cleared.
+#[cfg(debug_assertions)]
+const KNOWN_CAPACITY: usize = 1024;
+
+/// The lock-order checker of a lock.
+#[cfg_attr(not(debug_assertions), derive(Default))]
+pub(crate) struct LockOrder {
+    /// The identifier of the lock, unique among all locks of the process.
+    #[cfg(debug_assertions)]
+    id: usize,
+    #[cfg(debug_assertions)]
+    state: Arc<State>,
+}
+
+#[cfg(debug_assertions)]
+impl Default for LockOrder {
+    fn default() -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            state: Arc::default(),
+        }
+    }
+}
+
+impl fmt::Debug for LockOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockOrder").finish_non_exhaustive()
+    }
+}
+
+/// An acquisition of a lock that is in progress.
+pub(crate) struct Acquiring {
+    #[cfg(debug_assertions)]
+    location: &'static Location<'static>,
+    #[cfg(debug_assertions)]
+    acquisition: Option<Arc<Acquisition>>,
+}
+
+impl LockOrder {
+    /// Starts a blocking acquisition of the lock at `location`, checking that it may not deadlock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lock held by the current thread was acquired while holding the lock before,
+    /// possibly transitively.
+    pub(crate) fn lock(&self, location: &'static Location<'static>) -> Acquiring {
+        #[cfg(not(debug_assertions))]
+        let _ = location;
+
+        Acquiring {
+            #[cfg(debug_assertions)]
+            location,
+            #[cfg(debug_assertions)]
+            acquisition: self.check(location),
+        }
+    }
+
+    /// Starts a non-blocking acquisition of the lock at `location`.
+    pub(crate) fn try_lock(&self, location: &'static Location<'static>) -> Acquiring {
+        #[cfg(not(debug_assertions))]
+        let _ = location;
+
+        Acquiring {
+            #[cfg(debug_assertions)]
+            location,
+            #[cfg(debug_assertions)]
+            acquisition: self.nested(location),
+        }
+    }
+
+    /// Records that the lock is acquired by the current thread.
+    pub(crate) fn acquired(&self, acquiring: Acquiring) {
+        #[cfg(debug_assertions)]
+        {
+            // The thread-local may be destroyed if a guard is made by a thread-local destructor.
+            let _ = HELD.try_with(|held| prune(&mut held.borrow_mut()));
+            self.state
+                .holder
+                .store(current_thread_id(), Ordering::Relaxed);
+            let _ = HELD.try_with(|held| {
+                held.borrow_mut().push(Held {
+                    id: self.id,
+                    state: self.state.clone(),
+                    location: acquiring.location,
+                    acquisition: acquiring.acquisition,
+                });
+            });
+        }
+
+        #[cfg(not(debug_assertions))]
+        let _ = acquiring;
+    }
+
+    /// Records that the lock is released, possibly by a thread other than the acquiring one.
+    pub(crate) fn unlock(&self) {
+        #[cfg(debug_assertions)]
+        {
+            self.state.holder.store(0, Ordering::Relaxed);
+            let _ = HELD.try_with(|held| prune(&mut held.borrow_mut()));
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+impl LockOrder {
+    /// Returns the acquisition at `location` with a backtrace if the lock is nested with another
+    /// lock.
+    fn nested(&self, location: &'static Location<'static>) -> Option<Arc<Acquisition>> {
+        self.state
+            .ordered
+            .load(Ordering::Relaxed)
+            .then(|| capture(location))
+    }
+
+    /// Adds the edges from the locks held by the current thread to the lock, panicking if one of
+    /// them closes a cycle. Returns the acquisition at `location` if the lock is nested.
+    fn check(&self, location: &'static Location<'static>) -> Option<Arc<Acquisition>> {
+        // The lock may be held by the current thread if its guard was sent to another thread, so
+        // skip it.
+        let held = HELD
+            .try_with(|held| {
+                let mut held = held.borrow_mut();
+                prune(&mut held);
+                let known = KNOWN
+                    .try_with(|known| {
+                        let known = known.borrow();
+                        held.iter()
+                            .all(|held| held.id == self.id || known.contains(&(held.id, self.id)))
+                    })
+                    .unwrap_or(false);
+                if known {
+                    return Vec::new();
+                }
+
+                held.iter()
+                    .filter(|held| held.id != self.id)
+                    .map(|held| {
+                        (
+                            held.id,
+                            held.state.clone(),
+                            held.location,
+                            held.acquisition.clone(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if held.is_empty() {
+            return self.nested(location);
+        }
+
+        let acquisition = capture(location);
+        let mut graph = GRAPH.lock().unwrap_or_else(PoisonError::into_inner);
+        for (id, _, location, previous) in &held {
+            if graph
+                .get(id)
+                .is_some_and(|node| node.to.contains_key(&self.id))
+            {
+                continue;
+            }
+
+            let previous = previous.clone().unwrap_or_else(|| {
+                Arc::new(Acquisition {
+                    location,
+                    backtrace: None,
+                })
+            });
+            if let Some(path) = path(&graph, self.id, *id) {
+                let mut message = format!(
+                    "lock order inversion: lock #{} is acquired while holding lock #{id}\n\n\
+                     lock #{id} held since {previous}\n\nlock #{} acquiring at {acquisition}\n",
+                    self.id, self.id,
+                );
+                for (from, to) in path.iter().zip(&path[1..]) {
+                    let edge = &graph[from].to[to];
+                    message += &format!(
+                        "\nbut before, lock #{from} held since {}\n\n\
+                         lock #{to} acquired at {}\n",
+                        edge.held, edge.acquired,
+                    );
+                }
+
+                drop(graph);
+                panic!("{message}");
+            }
+
+            let _ = graph.entry(*id).or_default().to.insert(
+                self.id,
+                Edge {
+                    held: previous,
+                    acquired: acquisition.clone(),
+                },
+            );
+            let _ = graph.entry(self.id).or_default().from.insert(*id);
+        }
+        drop(graph);
+
+        // Every held lock now has an edge to the lock.
+        self.state.ordered.store(true, Ordering::Relaxed);
+        for (_, state, _, _) in &held {
+            state.ordered.store(true, Ordering::Relaxed);
+        }
+        let _ = KNOWN.try_with(|known| {
+            let mut known = known.borrow_mut();
+            if known.len() + held.len() > KNOWN_CAPACITY {
+                known.clear();
+            }
+            known.extend(held.iter().map(|(id, _, _, _)| (*id, self.id)));
+        });
+
+        Some(acquisition)
+    }
+}
+
+/// Returns the acquisition at `location` with the current backtrace.
+#[cfg(debug_assertions)]
+fn capture(location: &'static Location<'static>) -> Arc<Acquisition> {
+    Arc::new(Acquisition {
+        location,
+        backtrace: Some(Backtrace::capture()),
+    })
+}
+
+/// Removes the locks that are no longer held by the current thread, i.e. released by another
+/// thread. A lock acquired again by the current thread was released before, so it is pruned before
+/// it is pushed again.
+#[cfg(debug_assertions)]
+fn prune(held: &mut Vec<Held>) {
+    let thread = current_thread_id();
+    held.retain(|held| held.state.holder.load(Ordering::Relaxed) == thread);
+}
+
+/// Returns the locks on a path from `from` to `to` in the graph, if any.
+#[cfg(debug_assertions)]
+fn path(graph: &BTreeMap<usize, Node>, from: usize, to: usize) -> Option<Vec<usize>> {
+    let mut parents = BTreeMap::new();
+    let mut visited = BTreeSet::from([from]);
+    let mut stack = vec![from];
+
+    while let Some(id) = stack.pop() {
+        if id == to {
+            let mut path = vec![to];
+            while let Some(&parent) = parents.get(path.last().unwrap()) {
+                path.push(parent);
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        for &next in graph.get(&id).into_iter().flat_map(|node| node.to.keys()) {
+            if visited.insert(next) {
+                let _ = parents.insert(next, id);
+                stack.push(next);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(debug_assertions)]
+impl Drop for LockOrder {
+    fn drop(&mut self) {
+        // Identifiers are not reused, so only to free the memory.
+        if !self.state.ordered.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut graph = GRAPH.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(node) = graph.remove(&self.id) else {
+            return;
+        };
+        for from in node.from {
+            if let Some(from) = graph.get_mut(&from) {
+                let _ = from.to.remove(&self.id);
+            }
+        }
+        for to in node.to.into_keys() {
+            if let Some(to) = graph.get_mut(&to) {
+                let _ = to.from.remove(&self.id);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use core::time::Duration;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread::{self, scope};
+
+    use crate::lock::{lock_all, Lock, McsLock, PoisoningLock, SpinLock, TicketLock};
+
+    #[test]
+    fn consistent() {
+        let a = Lock::<SpinLock, usize>::new(0);
+        let b = Lock::<McsLock, usize>::new(0);
+        let c = Lock::<TicketLock, usize>::new(0);
+
+        scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..64 {
+                        let mut a = a.lock();
+                        let mut b = b.lock();
+                        *c.lock() += 1;
+                        *b += 1;
+                        drop(b);
+                        *c.lock() += 1;
+                        *a += 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(c.into_inner(), 512);
+    }
+
+    #[test]
+    #[should_panic(expected = "lock order inversion")]
+    fn inversion() {
+        let a = Lock::<SpinLock, usize>::new(0);
+        let b = Lock::<SpinLock, usize>::new(0);
+
+        // The second time, the edge is known by the thread.
+        for _ in 0..2 {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+
+        let _b = b.lock();
+        let _a = a.lock();
+    }
+
+    #[test]
+    fn backtraces() {
+        let a = Lock::<SpinLock, usize>::new(0);
+        let b = Lock::<SpinLock, usize>::new(0);
+
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+
+        // `b` is nested with `a`, so its unnested acquisition captures a backtrace.
+        let _b = b.lock();
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| drop(a.lock()))).unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        let (_, held) = message.split_once("held since ").unwrap();
+        let (held, _) = held.split_once("\n\n").unwrap();
+        assert!(held.contains('\n'), "no backtrace in {message}");
+    }
+
+    #[test]
+    #[should_panic(expected = "lock order inversion")]
+    fn poisoning() {
+        let a = PoisoningLock::<SpinLock, usize>::new(0);
+        let b = PoisoningLock::<SpinLock, usize>::new(0);
+
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+
+        let _b = b.lock();
+        let _a = a.lock();
+    }
+
+    #[test]
+    #[should_panic(expected = "lock order inversion")]
+    fn transitive() {
+        let locks = [(); 3].map(|_| Lock::<SpinLock, usize>::new(0));
+
+        scope(|s| {
+            for i in 0..2 {
+                let locks = &locks;
+                let _ = s
+                    .spawn(move || {
+                        let _first = locks[i].lock();
+                        let _second = locks[i + 1].lock();
+                    })
+                    .join();
+            }
+        });
+
+        let _last = locks[2].lock();
+        let _first = locks[0].lock();
+    }
+
+    #[test]
+    fn send_guard() {
+        let a = Lock::<SpinLock, usize>::new(0);
+        let b = Lock::<SpinLock, usize>::new(0);
+
+        // The guard of `a` is released by another thread, so it is not held while acquiring `b`
+        // and `a` again.
+        let guard = a.lock();
+        scope(|s| {
+            s.spawn(move || drop(guard));
+        });
+        {
+            let _b = b.lock();
+            let _a = a.lock();
+        }
+
+        // The guard of `a` is released by another thread while acquiring `a` again.
+        let guard = a.lock();
+        scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                drop(guard);
+            });
+            drop(a.lock());
+        });
+    }
+
+    #[test]
+    fn try_lock() {
+        let a = Lock::<SpinLock, usize>::new(0);
+        let b = Lock::<SpinLock, usize>::new(0);
+
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+
+        // Trying to acquire never deadlocks.
+        let b_guard = b.lock();
+        assert!(a.try_lock().is_ok());
+        drop(b_guard);
+
+        let (a, b) = lock_all((&a, &b));
+        drop((b, a));
+    }
+
+    #[test]
+    fn unlocked() {
+        let a = Lock::<SpinLock, usize>::new(0);
+        let b = Lock::<SpinLock, usize>::new(0);
+
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+
+        let mut b = b.lock();
+        b.unlocked(|| drop(a.lock()));
+    }
+}
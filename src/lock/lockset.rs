@@ -0,0 +1,278 @@
+use crossbeam_utils::Backoff;
+
+use crate::lock::*;
+
+/// A set of locks that are acquired together.
+///
+/// Acquiring all locks of a set at once never causes lock-ordering deadlocks with other threads
+/// doing the same, whatever order the locks are given in.
+pub trait LockSet<'s> {
+    /// The guards of the locks, in the order the locks are given in.
+    type Guards;
+
+    /// Acquires all locks in the global order of their addresses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the same lock is given more than once.
+    fn lock_all(self) -> Self::Guards;
+}
+
+/// A set of locks supporting the try_lock API, that are acquired together.
+pub trait TryLockSet<'s>: LockSet<'s> {
+    /// Tries to acquire all locks at once, releasing the acquired ones if any lock is held.
+    fn try_lock_all(self) -> Result<Self::Guards, ()>;
+
+    /// Acquires all locks without ordering them: waits for a lock, tries to acquire the others,
+    /// and if one of them is held, releases everything, backs off and retries starting from it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the same lock is given more than once.
+    fn lock_all_backoff(self) -> Self::Guards;
+}
+
+/// Acquires all locks of the given set in the global order of their addresses.
+#[track_caller]
+pub fn lock_all<'s, S: LockSet<'s>>(locks: S) -> S::Guards {
+    locks.lock_all()
+}
+
+fn addr<L: RawLock, T>(lock: &Lock<L, T>) -> usize {
+    lock as *const _ as usize
+}
+
+/// Returns the indices of the given addresses in the increasing order of the addresses.
+fn address_order(addrs: &[usize]) -> Vec<usize> {
+    let mut order = (0..addrs.len()).collect::<Vec<_>>();
+    order.sort_unstable_by_key(|&i| addrs[i]);
+    assert!(
+        order.windows(2).all(|w| addrs[w[0]] != addrs[w[1]]),
+        "the same lock is given more than once"
+    );
+    order
+}
+
+impl<'s, L: RawLock, T> LockSet<'s> for &[&'s Lock<L, T>] {
+    type Guards = Vec<LockGuard<'s, L, T>>;
+
+    #[track_caller]
+    fn lock_all(self) -> Self::Guards {
+        let addrs = self.iter().map(|l| addr(l)).collect::<Vec<_>>();
+        let mut guards = self.iter().map(|_| None).collect::<Vec<_>>();
+        for i in address_order(&addrs) {
+            guards[i] = Some(self[i].lock());
+        }
+        guards.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+impl<'s, L: RawTryLock, T> TryLockSet<'s> for &[&'s Lock<L, T>] {
+    #[track_caller]
+    fn try_lock_all(self) -> Result<Self::Guards, ()> {
+        let mut guards = Vec::with_capacity(self.len());
+        for lock in self {
+            guards.push(lock.try_lock()?);
+        }
+        Ok(guards)
+    }
+
+    #[track_caller]
+    fn lock_all_backoff(self) -> Self::Guards {
+        let addrs = self.iter().map(|l| addr(l)).collect::<Vec<_>>();
+        let _ = address_order(&addrs);
+
+        let backoff = Backoff::new();
+        let mut first = 0;
+        loop {
+            let mut guards = self.iter().map(|_| None).collect::<Vec<_>>();
+            guards[first] = Some(self[first].lock());
+
+            // Not a closure, so that the acquisitions are tracked to the caller.
+            let mut failed = None;
+            for i in (0..self.len()).filter(|&i| i != first) {
+                match self[i].try_lock() {
+                    Ok(guard) => guards[i] = Some(guard),
+                    Err(()) => {
+                        failed = Some(i);
+                        break;
+                    }
+                }
+            }
+
+            match failed {
+                None => return guards.into_iter().map(Option::unwrap).collect(),
+                Some(i) => {
+                    drop(guards);
+                    first = i;
+                    backoff.snooze();
+                }
+            }
+        }
+    }
+}
+
+macro_rules! impl_lock_set {
+    ($($i:tt $L:ident $T:ident),+) => {
+        impl<'s, $($L: RawLock, $T),+> LockSet<'s> for ($(&'s Lock<$L, $T>,)+) {
+            type Guards = ($(LockGuard<'s, $L, $T>,)+);
+
+            #[track_caller]
+            fn lock_all(self) -> Self::Guards {
+                let mut guards = ($(None::<LockGuard<'s, $L, $T>>,)+);
+                for i in address_order(&[$(addr(self.$i)),+]) {
+                    match i {
+                        $($i => guards.$i = Some(self.$i.lock()),)+
+                        _ => unreachable!(),
+                    }
+                }
+                ($(guards.$i.unwrap(),)+)
+            }
+        }
+
+        impl<'s, $($L: RawTryLock, $T),+> TryLockSet<'s> for ($(&'s Lock<$L, $T>,)+) {
+            #[track_caller]
+            fn try_lock_all(self) -> Result<Self::Guards, ()> {
+                Ok(($(self.$i.try_lock()?,)+))
+            }
+
+            #[track_caller]
+            fn lock_all_backoff(self) -> Self::Guards {
+                let addrs = [$(addr(self.$i)),+];
+                let _ = address_order(&addrs);
+
+                let backoff = Backoff::new();
+                let mut first = 0;
+                loop {
+                    let mut guards = ($(None::<LockGuard<'s, $L, $T>>,)+);
+                    match first {
+                        $($i => guards.$i = Some(self.$i.lock()),)+
+                        _ => unreachable!(),
+                    }
+
+                    // Not a closure, so that the acquisitions are tracked to the caller.
+                    let mut failed = None;
+                    for i in (0..addrs.len()).filter(|&i| i != first) {
+                        let acquired = match i {
+                            $($i => match self.$i.try_lock() {
+                                Ok(guard) => {
+                                    guards.$i = Some(guard);
+                                    true
+                                }
+                                Err(()) => false,
+                            },)+
+                            _ => unreachable!(),
+                        };
+                        if !acquired {
+                            failed = Some(i);
+                            break;
+                        }
+                    }
+
+                    match failed {
+                        None => return ($(guards.$i.unwrap(),)+),
+                        Some(i) => {
+                            drop(guards);
+                            first = i;
+                            backoff.snooze();
+                        }
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_lock_set!(0 L0 T0);
+impl_lock_set!(0 L0 T0, 1 L1 T1);
+impl_lock_set!(0 L0 T0, 1 L1 T1, 2 L2 T2);
+impl_lock_set!(0 L0 T0, 1 L1 T1, 2 L2 T2, 3 L3 T3);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::thread::scope;
+
+    use super::{lock_all, LockSet, TryLockSet};
+    use crate::lock::{Lock, SpinLock, TicketLock};
+
+    type Map = Lock<SpinLock, HashMap<usize, usize>>;
+
+    /// Moves entries back and forth between two maps, acquiring them in opposite orders.
+    fn transfer<F>(lock_both: F)
+    where
+        F: Fn(&Map, &Map, &mut dyn FnMut(&mut HashMap<usize, usize>, &mut HashMap<usize, usize>))
+            + Sync,
+    {
+        const THREADS: usize = 8;
+        const STEPS: usize = 4096;
+
+        let a = Map::new((0..THREADS).map(|i| (i, i)).collect());
+        let b = Map::new(HashMap::new());
+
+        scope(|s| {
+            for i in 0..THREADS {
+                let (a, b, lock_both) = (&a, &b, &lock_both);
+                s.spawn(move || {
+                    for step in 0..STEPS {
+                        let (from, to) = if (i + step) % 2 == 0 { (a, b) } else { (b, a) };
+                        lock_both(from, to, &mut |from, to| {
+                            if let Some(v) = from.remove(&i) {
+                                assert!(to.insert(i, v).is_none());
+                            }
+                        });
+                    }
+                });
+            }
+        });
+
+        let (a, b) = (a.into_inner(), b.into_inner());
+        assert_eq!(a.len() + b.len(), THREADS);
+    }
+
+    #[test]
+    fn lock_all_tuple() {
+        transfer(|from, to, f| {
+            let (mut from, mut to) = lock_all((from, to));
+            f(&mut from, &mut to);
+        });
+    }
+
+    #[test]
+    fn lock_all_backoff_tuple() {
+        transfer(|from, to, f| {
+            let (mut from, mut to) = (from, to).lock_all_backoff();
+            f(&mut from, &mut to);
+        });
+    }
+
+    #[test]
+    fn lock_all_slice() {
+        transfer(|from, to, f| {
+            let mut guards = [from, to][..].lock_all();
+            let (from, to) = guards.split_at_mut(1);
+            f(&mut from[0], &mut to[0]);
+        });
+    }
+
+    #[test]
+    fn try_lock_all() {
+        let a = Lock::<SpinLock, usize>::new(0);
+        let b = Lock::<TicketLock, &str>::new("b");
+
+        let guard = b.lock();
+        assert!((&a, &b).try_lock_all().is_err());
+        assert!(a.try_lock().is_ok());
+        drop(guard);
+
+        let (a, b) = (&a, &b).try_lock_all().unwrap();
+        assert_eq!((*a, *b), (0, "b"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn duplicate() {
+        let a = Lock::<SpinLock, usize>::new(0);
+        let _ = lock_all((&a, &a));
+    }
+}
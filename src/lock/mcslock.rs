@@ -0,0 +1,143 @@
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use crossbeam_utils::{Backoff, CachePadded};
+
+use crate::lock::*;
+
+#[derive(Debug)]
+struct Node {
+    locked: AtomicBool,
+    next: AtomicPtr<CachePadded<Node>>,
+}
+
+/// An MCS lock.
+///
+/// Each waiter spins on its own cache-padded queue node, which is handed back as the lock's token.
+#[derive(Debug)]
+pub struct McsLock {
+    tail: AtomicPtr<CachePadded<Node>>,
+}
+
+/// An MCS lock token, owning the acquirer's queue node.
+#[derive(Debug)]
+pub struct Token(*mut CachePadded<Node>);
+
+impl Node {
+    fn new() -> *mut CachePadded<Self> {
+        Box::into_raw(Box::new(CachePadded::new(Self {
+            locked: AtomicBool::new(true),
+            next: AtomicPtr::new(ptr::null_mut()),
+        })))
+    }
+}
+
+impl Default for McsLock {
+    fn default() -> Self {
+        Self {
+            tail: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl RawLock for McsLock {
+    type Token = Token;
+
+    fn lock(&self) -> Self::Token {
+        let node = Node::new();
+        let prev = self.tail.swap(node, Ordering::AcqRel);
+
+        if prev.is_null() {
+            return Token(node);
+        }
+
+        // SAFETY: `prev` is valid, as the lock holder or a waiter ahead of us does not free its
+        // node until it hands the lock over to its `next`, which is going to be us.
+        unsafe { &*prev }.next.store(node, Ordering::Release);
+
+        let backoff = Backoff::new();
+        // SAFETY: `node` is valid, as only its owner (us) frees it.
+        while unsafe { &*node }.locked.load(Ordering::Acquire) {
+            backoff.snooze();
+        }
+
+        Token(node)
+    }
+
+    unsafe fn unlock(&self, token: Self::Token) {
+        let node = token.0;
+        let mut next = (&*node).next.load(Ordering::Acquire);
+
+        if next.is_null() {
+            if self
+                .tail
+                .compare_exchange(node, ptr::null_mut(), Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                // SAFETY: `node` is unlinked from the queue, so nobody else refers to it.
+                drop(Box::from_raw(node));
+                return;
+            }
+
+            // A successor swapped the tail but has not linked itself yet.
+            let backoff = Backoff::new();
+            loop {
+                next = (&*node).next.load(Ordering::Acquire);
+                if !next.is_null() {
+                    break;
+                }
+                backoff.snooze();
+            }
+        }
+
+        // SAFETY: the successor already linked itself, so nobody else refers to `node`.
+        drop(Box::from_raw(node));
+        (&*next).locked.store(false, Ordering::Release);
+    }
+}
+
+impl RawTryLock for McsLock {
+    fn try_lock(&self) -> Result<Self::Token, ()> {
+        let node = Node::new();
+
+        match self.tail.compare_exchange(
+            ptr::null_mut(),
+            node,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => Ok(Token(node)),
+            Err(_) => {
+                // SAFETY: `node` was never published.
+                drop(unsafe { Box::from_raw(node) });
+                Err(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::api;
+    use super::mcslock::McsLock;
+    use crate::lock::{Lock, LockGuard};
+
+    #[test]
+    fn smoke() {
+        api::tests::smoke::<McsLock>();
+    }
+
+    #[test]
+    fn raw_roundtrip() {
+        let lock = Lock::<McsLock, usize>::new(0);
+        let (data, token) = lock.lock().into_raw_parts();
+        assert!(lock.try_lock().is_err());
+
+        // SAFETY: `data` and `token` come from the guard forgotten above.
+        let mut guard = unsafe { LockGuard::<McsLock, usize>::from_raw(data, token) };
+        *guard += 1;
+        drop(guard);
+
+        assert_eq!(*lock.try_lock().unwrap(), 1);
+    }
+}
@@ -2,7 +2,60 @@
 
 mod api;
 
+mod adaptivelock;
+mod atomiccell;
+mod backoff;
+mod brlock;
+mod clhlock;
+mod cohort;
+mod condvar;
+mod delegation;
+mod flatcombining;
+#[cfg(target_os = "linux")]
+mod futexlock;
+mod lockorder;
+mod lockset;
+mod mcslock;
+mod optlock;
+mod phasefairrwlock;
+mod poison;
+mod profiled;
+mod reentrantlock;
+pub mod rwlock;
 pub mod seqlock;
 mod spinlock;
+mod spinrwlock;
+mod striped;
+mod ticketlock;
+mod writerprefrwlock;
 
-pub use api::{Lock, LockGuard, RawLock, RawTryLock};
+pub use adaptivelock::AdaptiveSpinLock;
+pub use api::{Lock, LockGuard, MappedLockGuard, RawLock, RawTimedLock, RawTryLock};
+pub use atomiccell::AtomicCell;
+pub use backoff::{
+    BackoffPolicy, BoundedSpinBackoff, ExponentialBackoff, ParkBackoff, YieldBackoff,
+};
+pub use brlock::BrLock;
+pub use clhlock::{AbortableClhLock, ClhLock};
+pub use cohort::{cohort_group, set_cohort_group, Cohort, CohortToken};
+pub use condvar::Condvar;
+pub use delegation::Delegation;
+pub use flatcombining::FlatCombining;
+#[cfg(target_os = "linux")]
+pub use futexlock::FutexLock;
+pub use lockset::{lock_all, LockSet, TryLockSet};
+pub use mcslock::McsLock;
+pub use optlock::{OptLock, OptWriteGuard, Restart};
+pub use phasefairrwlock::PhaseFairRwLock;
+pub use poison::{
+    LockResult, PoisonError, PoisoningGuard, PoisoningLock, TryLockError, TryLockResult,
+};
+pub use profiled::{Profiled, ProfiledToken, Report, SiteReport};
+pub use reentrantlock::{ReentrantLock, ReentrantLockGuard};
+pub use rwlock::{RawRwLock, RawTryRwLock, RwLock};
+pub use seqlock::NoPadding;
+pub use spinlock::SpinLock;
+pub use spinrwlock::SpinRwLock;
+pub use striped::{StripeGuard, StripedLocks};
+pub use ticketlock::TicketLock;
+pub use writerprefrwlock::WriterPrefRwLock;
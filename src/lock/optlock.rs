@@ -0,0 +1,237 @@
+use core::mem;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use crossbeam_utils::Backoff;
+
+const OBSOLETE: usize = 0b01;
+const LOCKED: usize = 0b10;
+
+/// A signal that an optimistic operation should be restarted from a consistent state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Restart;
+
+/// An optimistic version lock for optimistic lock coupling.
+///
+/// Readers read a version, read the protected data with atomic accesses, and validate that the
+/// version did not change in the meantime. Writers lock the version, possibly by upgrading a read.
+/// When a node is deleted, the writer marks its lock obsolete so that readers still traversing it
+/// restart.
+///
+/// See Leis et al., "The ART of Practical Synchronization", DaMoN 2016.
+#[derive(Debug)]
+pub struct OptLock {
+    /// The lowest bit is set once obsolete, the next bit is set while locked, and the other bits
+    /// count the writes.
+    version: AtomicUsize,
+}
+
+/// A writer's guard of an optimistic version lock.
+///
+/// Dropping the guard releases the lock and bumps the version.
+#[derive(Debug)]
+pub struct OptWriteGuard<'s> {
+    lock: &'s OptLock,
+}
+
+impl Default for OptLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OptLock {
+    /// Creates a new optimistic version lock.
+    pub const fn new() -> Self {
+        Self {
+            version: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns whether the lock is marked obsolete.
+    pub fn is_obsolete(&self) -> bool {
+        self.version.load(Ordering::Relaxed) & OBSOLETE != 0
+    }
+
+    /// Begins a read, waiting for the writer if any. Returns the version to validate the read
+    /// with, or restarts if the lock is obsolete.
+    pub fn read_version(&self) -> Result<usize, Restart> {
+        let backoff = Backoff::new();
+
+        loop {
+            let version = self.version.load(Ordering::Acquire);
+            if version & OBSOLETE != 0 {
+                return Err(Restart);
+            }
+            if version & LOCKED == 0 {
+                return Ok(version);
+            }
+
+            backoff.snooze();
+        }
+    }
+
+    /// Validates the reads since `read_version()` returned `version`. Restarts if a writer
+    /// intervened.
+    pub fn validate(&self, version: usize) -> Result<(), Restart> {
+        fence(Ordering::Acquire);
+
+        if version == self.version.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            Err(Restart)
+        }
+    }
+
+    /// Upgrades the read that began at `version` to a write. Restarts if a writer intervened, or
+    /// if `version` is not a version returned by `read_version()`, i.e. locked or obsolete.
+    pub fn upgrade_to_write(&self, version: usize) -> Result<OptWriteGuard<'_>, Restart> {
+        if version & (LOCKED | OBSOLETE) != 0 {
+            return Err(Restart);
+        }
+
+        self.version
+            .compare_exchange(
+                version,
+                version | LOCKED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .map_err(|_| Restart)?;
+
+        fence(Ordering::Release);
+        Ok(OptWriteGuard { lock: self })
+    }
+
+    /// Acquires the lock for writing. Restarts if the lock is obsolete.
+    pub fn write_lock(&self) -> Result<OptWriteGuard<'_>, Restart> {
+        let backoff = Backoff::new();
+
+        loop {
+            let version = self.read_version()?;
+            if let Ok(guard) = self.upgrade_to_write(version) {
+                return Ok(guard);
+            }
+
+            backoff.snooze();
+        }
+    }
+}
+
+impl<'s> OptWriteGuard<'s> {
+    /// Releases the lock and marks it obsolete, e.g. when the protected node is unlinked.
+    pub fn mark_obsolete(self) {
+        self.lock
+            .version
+            .fetch_add(LOCKED | OBSOLETE, Ordering::Release);
+        mem::forget(self);
+    }
+
+    /// Releases the lock and begins a read that sees the writes. Returns the version to validate
+    /// the read with.
+    pub fn downgrade(self) -> usize {
+        let version = self.lock.version.fetch_add(LOCKED, Ordering::Release);
+        mem::forget(self);
+        version.wrapping_add(LOCKED)
+    }
+}
+
+impl<'s> Drop for OptWriteGuard<'s> {
+    fn drop(&mut self) {
+        self.lock.version.fetch_add(LOCKED, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::scope;
+
+    use super::{OptLock, Restart};
+
+    #[derive(Debug, Default)]
+    struct Node {
+        lock: OptLock,
+        left: AtomicUsize,
+        right: AtomicUsize,
+    }
+
+    impl Node {
+        /// Optimistically reads both fields, which are always equal.
+        fn read(&self) -> Result<(usize, usize), Restart> {
+            let version = self.lock.read_version()?;
+            let left = self.left.load(Ordering::Relaxed);
+            let right = self.right.load(Ordering::Relaxed);
+            self.lock.validate(version)?;
+            Ok((left, right))
+        }
+
+        fn increment(&self) -> Result<(), Restart> {
+            let version = self.lock.read_version()?;
+            let left = self.left.load(Ordering::Relaxed);
+            let _guard = self.lock.upgrade_to_write(version)?;
+            self.left.store(left + 1, Ordering::Relaxed);
+            self.right.store(left + 1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn optimistic() {
+        const THREADS: usize = 4;
+        const STEPS: usize = 4096;
+
+        let node = Node::default();
+
+        scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..STEPS {
+                        while node.increment().is_err() {}
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..STEPS {
+                        if let Ok((left, right)) = node.read() {
+                            assert_eq!(left, right);
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(node.read(), Ok((THREADS * STEPS, THREADS * STEPS)));
+    }
+
+    #[test]
+    fn obsolete() {
+        let lock = OptLock::new();
+
+        let version = lock.read_version().unwrap();
+        let guard = lock.upgrade_to_write(version).unwrap();
+        let version = guard.downgrade();
+        assert_eq!(lock.validate(version), Ok(()));
+
+        lock.write_lock().unwrap().mark_obsolete();
+        assert!(lock.is_obsolete());
+        assert_eq!(lock.validate(version), Err(Restart));
+        assert_eq!(lock.read_version(), Err(Restart));
+        assert!(lock.write_lock().is_err());
+    }
+
+    #[test]
+    fn upgrade_invalid_version() {
+        let lock = OptLock::new();
+
+        let version = lock.read_version().unwrap();
+        let guard = lock.upgrade_to_write(version).unwrap();
+        let locked = lock.version.load(Ordering::Relaxed);
+        assert!(lock.upgrade_to_write(locked).is_err());
+        drop(guard);
+
+        let version = lock.read_version().unwrap();
+        lock.write_lock().unwrap().mark_obsolete();
+        let obsolete = lock.version.load(Ordering::Relaxed);
+        assert!(lock.upgrade_to_write(obsolete).is_err());
+        assert!(lock.upgrade_to_write(version).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crossbeam_utils::{Backoff, CachePadded};
+
+use crate::lock::*;
+
+/// The increment of the reader counts.
+const READER: usize = 0x100;
+/// The bits of `rin` that are set while a writer is present.
+const WRITER_BITS: usize = 0b11;
+/// Set while a writer is present.
+const PRESENT: usize = 0b10;
+/// The phase of the present writer, so that readers can tell consecutive writers apart.
+const PHASE: usize = 0b01;
+
+/// A phase-fair ticket reader-writer lock.
+///
+/// Reader phases and writer phases alternate: a writer waits for at most one reader phase, and a
+/// reader waits for at most one writer phase. Writers are ordered among themselves by tickets.
+///
+/// See Brandenburg and Anderson, "Reader-Writer Synchronization for Shared-Memory Multiprocessor
+/// Real-Time Systems", ECRTS 2009.
+#[derive(Debug)]
+pub struct PhaseFairRwLock {
+    /// The readers that entered, and the writer bits.
+    rin: CachePadded<AtomicUsize>,
+    /// The readers that left.
+    rout: CachePadded<AtomicUsize>,
+    /// The next writer's ticket.
+    win: CachePadded<AtomicUsize>,
+    /// The ticket of the writer that is allowed to proceed.
+    wout: CachePadded<AtomicUsize>,
+}
+
+impl Default for PhaseFairRwLock {
+    fn default() -> Self {
+        Self {
+            rin: CachePadded::new(AtomicUsize::new(0)),
+            rout: CachePadded::new(AtomicUsize::new(0)),
+            win: CachePadded::new(AtomicUsize::new(0)),
+            wout: CachePadded::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl RawRwLock for PhaseFairRwLock {
+    type ReadToken = ();
+    type WriteToken = ();
+
+    fn read_lock(&self) {
+        let writer = self.rin.fetch_add(READER, Ordering::Acquire) & WRITER_BITS;
+        if writer == 0 {
+            return;
+        }
+
+        // Wait for the present writer's phase to end, even if the next writer is present by then.
+        let backoff = Backoff::new();
+        while self.rin.load(Ordering::Acquire) & WRITER_BITS == writer {
+            backoff.snooze();
+        }
+    }
+
+    unsafe fn read_unlock(&self, _token: ()) {
+        self.rout.fetch_add(READER, Ordering::Release);
+    }
+
+    fn write_lock(&self) {
+        let ticket = self.win.fetch_add(1, Ordering::Relaxed);
+
+        let backoff = Backoff::new();
+        while self.wout.load(Ordering::Acquire) != ticket {
+            backoff.snooze();
+        }
+
+        // Block new readers, and wait for the readers that entered before.
+        let readers = self
+            .rin
+            .fetch_add(PRESENT | (ticket & PHASE), Ordering::Relaxed);
+
+        let backoff = Backoff::new();
+        while self.rout.load(Ordering::Acquire) != readers {
+            backoff.snooze();
+        }
+    }
+
+    unsafe fn write_unlock(&self, _token: ()) {
+        self.rin.fetch_and(!WRITER_BITS, Ordering::Release);
+        self.wout.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::Ordering;
+
+    use super::super::rwlock;
+    use super::phasefairrwlock::{PhaseFairRwLock, PRESENT};
+
+    #[test]
+    fn smoke() {
+        rwlock::tests::smoke::<PhaseFairRwLock>();
+    }
+
+    #[test]
+    fn log_concurrent() {
+        rwlock::tests::log_concurrent::<PhaseFairRwLock>();
+    }
+
+    #[test]
+    fn max_writer_wait() {
+        let _wait = rwlock::tests::max_writer_wait::<PhaseFairRwLock>();
+    }
+
+    #[test]
+    fn writer_preference() {
+        rwlock::tests::writer_preference::<PhaseFairRwLock>(|lock| {
+            lock.rin.load(Ordering::Relaxed) & PRESENT != 0
+        });
+    }
+}
@@ -0,0 +1,234 @@
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::error::Error;
+use std::thread;
+
+use crate::lock::*;
+
+/// An error returned when a lock is poisoned, i.e. a thread panicked while holding it.
+///
+/// The error still holds the acquired guard, so the caller may inspect or repair the data.
+pub struct PoisonError<G> {
+    guard: G,
+}
+
+/// A result of acquiring a poisoning lock.
+pub type LockResult<G> = Result<G, PoisonError<G>>;
+
+/// A result of trying to acquire a poisoning lock.
+pub type TryLockResult<G> = Result<G, TryLockError<G>>;
+
+/// An error returned by `PoisoningLock::try_lock()`.
+pub enum TryLockError<G> {
+    /// The lock was acquired, but it is poisoned.
+    Poisoned(PoisonError<G>),
+    /// The lock is held by another thread.
+    WouldBlock,
+}
+
+impl<G> PoisonError<G> {
+    /// Retrieves the guard, ignoring the poison.
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+
+    /// Dereferences the guard, ignoring the poison.
+    pub fn get_ref(&self) -> &G {
+        &self.guard
+    }
+
+    /// Dereferences the guard, ignoring the poison.
+    pub fn get_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+}
+
+impl<G> fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+impl<G> fmt::Display for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("poisoned lock: another thread panicked while holding it")
+    }
+}
+
+impl<G> Error for PoisonError<G> {}
+
+impl<G> fmt::Debug for TryLockError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Poisoned(e) => f.debug_tuple("Poisoned").field(e).finish(),
+            Self::WouldBlock => f.write_str("WouldBlock"),
+        }
+    }
+}
+
+/// A type-safe lock that is poisoned when a thread panics while holding it.
+#[derive(Debug)]
+pub struct PoisoningLock<L: RawLock, T> {
+    poisoned: AtomicBool,
+    lock: Lock<L, T>,
+}
+
+/// A guard that holds the poisoning lock and dereferences the inner value.
+///
+/// The lock is poisoned if the guard is dropped while the thread is panicking.
+pub struct PoisoningGuard<'s, L: RawLock, T> {
+    poisoned: &'s AtomicBool,
+    // Whether the thread was already panicking when the guard was created.
+    panicking: bool,
+    guard: LockGuard<'s, L, T>,
+}
+
+impl<L: RawLock, T: Default> Default for PoisoningLock<L, T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<L: RawLock, T> PoisoningLock<L, T> {
+    /// Creates a new poisoning lock.
+    pub fn new(data: T) -> Self {
+        Self {
+            poisoned: AtomicBool::new(false),
+            lock: Lock::new(data),
+        }
+    }
+
+    /// Returns whether the lock is poisoned.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Clears the poison, e.g. after the lock-protected value is repaired.
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    /// Destroys the lock and retrieves the lock-protected value.
+    pub fn into_inner(self) -> Result<T, PoisonError<T>> {
+        let poisoned = self.is_poisoned();
+        let data = self.lock.into_inner();
+        if poisoned {
+            Err(PoisonError { guard: data })
+        } else {
+            Ok(data)
+        }
+    }
+
+    /// Dereferences the inner value.
+    pub fn get_mut(&mut self) -> Result<&mut T, PoisonError<&mut T>> {
+        let poisoned = self.is_poisoned();
+        let data = self.lock.get_mut();
+        if poisoned {
+            Err(PoisonError { guard: data })
+        } else {
+            Ok(data)
+        }
+    }
+
+    fn guard<'s>(&'s self, guard: LockGuard<'s, L, T>) -> LockResult<PoisoningGuard<'s, L, T>> {
+        let guard = PoisoningGuard {
+            poisoned: &self.poisoned,
+            panicking: thread::panicking(),
+            guard,
+        };
+
+        if self.is_poisoned() {
+            Err(PoisonError { guard })
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Acquires the lock and dereferences the inner value.
+    #[track_caller]
+    pub fn lock(&self) -> LockResult<PoisoningGuard<'_, L, T>> {
+        self.guard(self.lock.lock())
+    }
+}
+
+impl<L: RawTryLock, T> PoisoningLock<L, T> {
+    /// Tries to acquire the lock and dereferences the inner value.
+    #[track_caller]
+    pub fn try_lock(&self) -> TryLockResult<PoisoningGuard<'_, L, T>> {
+        let guard = self.lock.try_lock().map_err(|_| TryLockError::WouldBlock)?;
+        self.guard(guard).map_err(TryLockError::Poisoned)
+    }
+}
+
+impl<'s, L: RawLock, T: fmt::Debug> fmt::Debug for PoisoningGuard<'s, L, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisoningGuard")
+            .field("data", &**self)
+            .finish()
+    }
+}
+
+impl<'s, L: RawLock, T> Drop for PoisoningGuard<'s, L, T> {
+    fn drop(&mut self) {
+        if !self.panicking && thread::panicking() {
+            self.poisoned.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<'s, L: RawLock, T> Deref for PoisoningGuard<'s, L, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<'s, L: RawLock, T> DerefMut for PoisoningGuard<'s, L, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use super::{PoisoningLock, TryLockError};
+    use crate::lock::SpinLock;
+
+    #[test]
+    fn poison() {
+        let lock = PoisoningLock::<SpinLock, Vec<usize>>::new(vec![]);
+
+        lock.lock().unwrap().push(1);
+        assert!(!lock.is_poisoned());
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut guard = lock.lock().unwrap();
+            guard.push(2);
+            panic!("half-updated");
+        }));
+        assert!(result.is_err());
+        assert!(lock.is_poisoned());
+
+        // The data is still accessible through the error.
+        let mut guard = lock.lock().unwrap_err().into_inner();
+        assert_eq!(*guard, vec![1, 2]);
+        let _ = guard.pop();
+        drop(guard);
+
+        assert!(matches!(lock.try_lock(), Err(TryLockError::Poisoned(_))));
+        lock.clear_poison();
+        assert!(lock.try_lock().is_ok());
+        assert_eq!(lock.into_inner().unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn would_block() {
+        let lock = PoisoningLock::<SpinLock, usize>::new(0);
+        let _guard = lock.lock().unwrap();
+        assert!(matches!(lock.try_lock(), Err(TryLockError::WouldBlock)));
+    }
+}
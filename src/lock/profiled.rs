@@ -0,0 +1,460 @@
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crossbeam_utils::CachePadded;
+
+use crate::lock::*;
+
+/// The number of buckets of a histogram.
+const BUCKETS: usize = 64;
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+#[derive(Debug)]
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+        }
+    }
+}
+
+impl Histogram {
+    fn record(&self, nanos: u64) {
+        let bucket = (u64::BITS - nanos.leading_zeros()).saturating_sub(1);
+        let _ = self.buckets[bucket as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> [u64; BUCKETS] {
+        core::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed))
+    }
+}
+
+/// The statistics of the acquisitions at a call site.
+#[derive(Debug, Default)]
+struct SiteStats {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    failed_tries: AtomicU64,
+    wait_nanos: AtomicU64,
+    hold_nanos: AtomicU64,
+    wait: Histogram,
+    hold: Histogram,
+}
+
+thread_local! {
+    /// The statistics of the call sites of the profiled locks, indexed by the identifiers of the
+    /// locks and the call sites, so that acquisitions do not look up the shared table of a lock.
+    /// Identifiers are not reused, so the entries of dropped locks are never looked up.
+    static SITES: RefCell<HashMap<(usize, &'static Location<'static>), *const SiteStats>> =
+        RefCell::new(HashMap::new());
+}
+
+/// The number of entries in `SITES` above which it is cleared, to free the entries of dropped
+/// locks.
+const SITES_CAPACITY: usize = 1024;
+
+/// A raw lock that records the statistics of its acquisitions per call site.
+///
+/// The call site is the caller of `Lock::lock()` or `Lock::try_lock()`, or of `RawLock::lock()` or
+/// `RawTryLock::try_lock()` if called directly. An acquisition is contended if another thread held
+/// the lock when it began.
+#[derive(Debug)]
+pub struct Profiled<L: RawLock> {
+    lock: L,
+    /// Whether the lock is held. Only the holder writes it, so that acquirers only read it.
+    held: CachePadded<AtomicBool>,
+    /// The identifier of the lock, unique among all profiled locks of the process.
+    id: usize,
+    /// The statistics of the call sites. They are boxed and never removed, so that threads cache
+    /// pointers to them.
+    sites: RwLock<SpinRwLock, HashMap<&'static Location<'static>, Box<SiteStats>>>,
+}
+
+impl<L: RawLock> Default for Profiled<L> {
+    fn default() -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        Self {
+            lock: L::default(),
+            held: CachePadded::new(AtomicBool::new(false)),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            sites: RwLock::default(),
+        }
+    }
+}
+
+/// A profiled lock token.
+pub struct ProfiledToken<L: RawLock> {
+    token: L::Token,
+    /// The statistics of the call site, owned by the lock.
+    site: *const SiteStats,
+    acquired: Instant,
+}
+
+unsafe impl<L: RawLock> Send for ProfiledToken<L> where L::Token: Send {}
+unsafe impl<L: RawLock> Sync for ProfiledToken<L> where L::Token: Sync {}
+
+impl<L: RawLock> fmt::Debug for ProfiledToken<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProfiledToken")
+            .field("acquired", &self.acquired)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<L: RawLock> Profiled<L> {
+    fn site(&self, location: &'static Location<'static>) -> &SiteStats {
+        let key = (self.id, location);
+        let site = SITES
+            .with(|sites| sites.borrow().get(&key).copied())
+            .unwrap_or_else(|| {
+                let site = &**self.sites.write().entry(location).or_default() as *const SiteStats;
+                SITES.with(|sites| {
+                    let mut sites = sites.borrow_mut();
+                    if sites.len() >= SITES_CAPACITY {
+                        sites.clear();
+                    }
+                    sites.insert(key, site)
+                });
+                site
+            });
+
+        // SAFETY: `self.id` is unique, so `site` is of this lock, which owns it until dropped.
+        unsafe { &*site }
+    }
+
+    fn acquired(&self, token: L::Token, site: &SiteStats, wait: Duration) -> ProfiledToken<L> {
+        self.held.store(true, Ordering::Relaxed);
+
+        let wait = nanos(wait);
+        let _ = site.acquisitions.fetch_add(1, Ordering::Relaxed);
+        let _ = site.wait_nanos.fetch_add(wait, Ordering::Relaxed);
+        site.wait.record(wait);
+
+        ProfiledToken {
+            token,
+            site,
+            acquired: Instant::now(),
+        }
+    }
+
+    /// Returns the statistics of all call sites, the site with the longest total wait first.
+    pub fn report(&self) -> Report {
+        let mut sites = self
+            .sites
+            .read()
+            .iter()
+            .map(|(location, site)| SiteReport {
+                location,
+                acquisitions: site.acquisitions.load(Ordering::Relaxed),
+                contended: site.contended.load(Ordering::Relaxed),
+                failed_tries: site.failed_tries.load(Ordering::Relaxed),
+                wait: Duration::from_nanos(site.wait_nanos.load(Ordering::Relaxed)),
+                hold: Duration::from_nanos(site.hold_nanos.load(Ordering::Relaxed)),
+                wait_histogram: site.wait.snapshot(),
+                hold_histogram: site.hold.snapshot(),
+            })
+            .collect::<Vec<_>>();
+
+        sites.sort_by(|a, b| b.wait.cmp(&a.wait).then(b.hold.cmp(&a.hold)));
+        Report { sites }
+    }
+}
+
+impl<L: RawLock> RawLock for Profiled<L> {
+    type Token = ProfiledToken<L>;
+
+    #[track_caller]
+    fn lock(&self) -> Self::Token {
+        let site = self.site(Location::caller());
+        if self.held.load(Ordering::Relaxed) {
+            let _ = site.contended.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let start = Instant::now();
+        let token = self.lock.lock();
+        self.acquired(token, site, start.elapsed())
+    }
+
+    unsafe fn unlock(&self, token: Self::Token) {
+        // SAFETY: the token is from this lock, which owns the statistics.
+        let site = unsafe { &*token.site };
+        let hold = nanos(token.acquired.elapsed());
+        let _ = site.hold_nanos.fetch_add(hold, Ordering::Relaxed);
+        site.hold.record(hold);
+
+        self.held.store(false, Ordering::Relaxed);
+        self.lock.unlock(token.token);
+    }
+}
+
+impl<L: RawTryLock> RawTryLock for Profiled<L> {
+    #[track_caller]
+    fn try_lock(&self) -> Result<Self::Token, ()> {
+        let site = self.site(Location::caller());
+        match self.lock.try_lock() {
+            Ok(token) => Ok(self.acquired(token, site, Duration::ZERO)),
+            Err(()) => {
+                let _ = site.failed_tries.fetch_add(1, Ordering::Relaxed);
+                Err(())
+            }
+        }
+    }
+}
+
+/// The statistics of the acquisitions of a profiled lock at a call site.
+#[derive(Debug, Clone)]
+pub struct SiteReport {
+    /// The call site.
+    pub location: &'static Location<'static>,
+    /// The number of acquisitions.
+    pub acquisitions: u64,
+    /// The number of acquisitions that began while the lock was held.
+    pub contended: u64,
+    /// The number of failed `try_lock()`s.
+    pub failed_tries: u64,
+    /// The total time waited for the lock.
+    pub wait: Duration,
+    /// The total time the lock was held.
+    pub hold: Duration,
+    /// The histogram of the wait times. The bucket `i` counts the waits in `[2^i, 2^(i+1))`
+    /// nanoseconds, and the bucket 0 also counts zero.
+    pub wait_histogram: [u64; BUCKETS],
+    /// The histogram of the hold times, bucketed as `wait_histogram`.
+    pub hold_histogram: [u64; BUCKETS],
+}
+
+/// Returns the upper bound of the given percentile of the histogram.
+fn percentile(histogram: &[u64; BUCKETS], percent: u64) -> Duration {
+    let total = histogram.iter().sum::<u64>();
+    let rank = (total * percent).div_ceil(100);
+
+    let mut count = 0;
+    for (i, n) in histogram.iter().enumerate() {
+        count += n;
+        if count >= rank.max(1) {
+            return Duration::from_nanos(2u64.saturating_pow(i as u32 + 1));
+        }
+    }
+    Duration::ZERO
+}
+
+impl SiteReport {
+    /// Returns the upper bound of the given percentile of the wait times.
+    pub fn wait_percentile(&self, percent: u64) -> Duration {
+        percentile(&self.wait_histogram, percent)
+    }
+
+    /// Returns the upper bound of the given percentile of the hold times.
+    pub fn hold_percentile(&self, percent: u64) -> Duration {
+        percentile(&self.hold_histogram, percent)
+    }
+}
+
+impl fmt::Display for SiteReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} acquisitions, {} contended, {} failed tries, \
+             wait {:?} (p50 < {:?}, p99 < {:?}), hold {:?} (p50 < {:?}, p99 < {:?})",
+            self.location,
+            self.acquisitions,
+            self.contended,
+            self.failed_tries,
+            self.wait,
+            self.wait_percentile(50),
+            self.wait_percentile(99),
+            self.hold,
+            self.hold_percentile(50),
+            self.hold_percentile(99),
+        )
+    }
+}
+
+/// The statistics of the acquisitions of a profiled lock per call site.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// The statistics of the call sites, the site with the longest total wait first.
+    pub sites: Vec<SiteReport>,
+}
+
+impl Report {
+    /// Keeps only the `n` sites with the longest total waits.
+    pub fn top(mut self, n: usize) -> Self {
+        self.sites.truncate(n);
+        self
+    }
+
+    /// Formats the report as a JSON array of sites. Durations are in nanoseconds.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        for (i, site) in self.sites.iter().enumerate() {
+            if i != 0 {
+                json.push(',');
+            }
+
+            let file = site
+                .location
+                .file()
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"");
+            let _ = write!(
+                json,
+                "{{\"file\":\"{}\",\"line\":{},\"column\":{},\"acquisitions\":{},\"contended\":{},\
+                 \"failed_tries\":{},\"wait_nanos\":{},\"hold_nanos\":{},\
+                 \"wait_histogram\":{:?},\"hold_histogram\":{:?}}}",
+                file,
+                site.location.line(),
+                site.location.column(),
+                site.acquisitions,
+                site.contended,
+                site.failed_tries,
+                site.wait.as_nanos(),
+                site.hold.as_nanos(),
+                site.wait_histogram,
+                site.hold_histogram,
+            );
+        }
+        json.push(']');
+        json
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for site in &self.sites {
+            writeln!(f, "{site}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+    use std::thread::{self, scope};
+
+    use super::super::api;
+    use super::profiled::Profiled;
+    use crate::lock::{lock_all, Condvar, Lock, LockSet, SpinLock, TicketLock, TryLockSet};
+
+    #[test]
+    fn smoke() {
+        api::tests::smoke::<Profiled<SpinLock>>();
+        api::tests::smoke::<Profiled<TicketLock>>();
+    }
+
+    #[test]
+    fn report() {
+        const THREADS: usize = 4;
+        const STEPS: usize = 64;
+
+        let lock = Lock::<Profiled<SpinLock>, usize>::new(0);
+
+        scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..STEPS {
+                        let mut guard = lock.lock();
+                        thread::sleep(Duration::from_micros(10));
+                        *guard += 1;
+                    }
+                });
+            }
+        });
+
+        let guard = lock.lock();
+        assert!(lock.try_lock().is_err());
+        drop(guard);
+        let line = line!() + 1;
+        drop(lock.try_lock().unwrap());
+
+        let report = lock.raw_lock().report();
+        assert_eq!(report.sites.len(), 4);
+
+        let hot = &report.sites[0];
+        assert_eq!(hot.acquisitions, (THREADS * STEPS) as u64);
+        assert!(hot.contended > 0);
+        assert!(hot.hold >= Duration::from_micros(10) * (THREADS * STEPS) as u32);
+        assert_eq!(hot.hold_histogram.iter().sum::<u64>(), hot.acquisitions);
+
+        let tried = report
+            .sites
+            .iter()
+            .find(|site| site.location.line() == line)
+            .unwrap();
+        assert_eq!((tried.acquisitions, tried.failed_tries), (1, 0));
+        assert_eq!(
+            report
+                .sites
+                .iter()
+                .map(|site| site.failed_tries)
+                .sum::<u64>(),
+            1
+        );
+
+        let top = report.top(1);
+        assert_eq!(top.sites.len(), 1);
+        assert!(top.to_string().starts_with(file!()));
+        assert!(top
+            .to_json()
+            .starts_with(&format!("[{{\"file\":\"{}\"", file!())));
+    }
+
+    #[test]
+    fn sites_per_lock() {
+        let locks = [(); 2].map(|_| Lock::<Profiled<SpinLock>, usize>::new(0));
+
+        for _ in 0..2 {
+            for lock in &locks {
+                *lock.lock() += 1;
+            }
+        }
+
+        for lock in &locks {
+            let report = lock.raw_lock().report();
+            assert_eq!(report.sites.len(), 1);
+            assert_eq!(report.sites[0].acquisitions, 2);
+        }
+    }
+    #[test]
+    fn wrapper_sites() {
+        let a = Lock::<Profiled<SpinLock>, usize>::new(0);
+        let b = Lock::<Profiled<SpinLock>, usize>::new(0);
+        let condvar = Condvar::new();
+
+        let mut guard = a.lock();
+        guard.unlocked(|| ());
+        let _ = condvar.wait_timeout(&mut guard, Duration::ZERO);
+        drop(guard);
+        drop(b.lock());
+
+        drop(lock_all((&a, &b)));
+        drop((&a, &b).try_lock_all().unwrap());
+        drop((&a, &b).lock_all_backoff());
+        drop([&a, &b][..].lock_all());
+        drop([&a, &b][..].try_lock_all().unwrap());
+        drop([&a, &b][..].lock_all_backoff());
+
+        // The wrappers report the sites in this file, not their own.
+        for lock in [&a, &b] {
+            let report = lock.raw_lock().report();
+            assert!(report
+                .sites
+                .iter()
+                .all(|site| site.location.file() == file!()));
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::lock::*;
+
+/// Returns a nonzero identifier of the current thread, unique among all threads of the process.
+pub(crate) fn current_thread_id() -> usize {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+    thread_local! {
+        static ID: usize = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    }
+
+    ID.with(|id| *id)
+}
+
+/// A type-safe lock that may be acquired multiple times by the thread holding it.
+///
+/// Since the same value may be borrowed multiple times, guards only give shared access.
+#[derive(Debug)]
+pub struct ReentrantLock<L: RawLock, T> {
+    lock: L,
+    /// The identifier of the holding thread, or 0 if the lock is not held.
+    owner: AtomicUsize,
+    /// The recursion count and the underlying lock's token. Only accessed by the holding thread.
+    state: UnsafeCell<(usize, Option<L::Token>)>,
+    data: T,
+}
+
+unsafe impl<L: RawLock, T: Send> Send for ReentrantLock<L, T> {}
+unsafe impl<L: RawLock, T: Send> Sync for ReentrantLock<L, T> {}
+
+impl<L: RawLock, T: Default> Default for ReentrantLock<L, T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<L: RawLock, T> ReentrantLock<L, T> {
+    /// Creates a new reentrant lock.
+    pub fn new(data: T) -> Self {
+        Self {
+            lock: L::default(),
+            owner: AtomicUsize::new(0),
+            state: UnsafeCell::new((0, None)),
+            data,
+        }
+    }
+
+    /// Destroys the lock and retrieves the lock-protected value.
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+
+    /// Dereferences the inner value.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+
+    /// Tries to reacquire the lock if the current thread already holds it.
+    fn reacquire(&self, id: usize) -> Option<ReentrantLockGuard<'_, L, T>> {
+        // `Relaxed` suffices, since only the current thread may have stored its own `id`.
+        if self.owner.load(Ordering::Relaxed) != id {
+            return None;
+        }
+
+        // SAFETY: the current thread holds the lock, so it has unique access to `state`.
+        let state = unsafe { &mut *self.state.get() };
+        state.0 = state.0.checked_add(1).expect("lock count overflow");
+        Some(ReentrantLockGuard::new(self))
+    }
+
+    /// Records the current thread as the holder of the freshly acquired lock.
+    fn acquired(&self, id: usize, token: L::Token) -> ReentrantLockGuard<'_, L, T> {
+        self.owner.store(id, Ordering::Relaxed);
+
+        // SAFETY: the current thread holds the lock, so it has unique access to `state`.
+        unsafe { *self.state.get() = (1, Some(token)) };
+        ReentrantLockGuard::new(self)
+    }
+
+    /// Acquires the lock and dereferences the inner value.
+    #[track_caller]
+    pub fn lock(&self) -> ReentrantLockGuard<'_, L, T> {
+        let id = current_thread_id();
+        if let Some(guard) = self.reacquire(id) {
+            return guard;
+        }
+
+        let token = self.lock.lock();
+        self.acquired(id, token)
+    }
+}
+
+impl<L: RawTryLock, T> ReentrantLock<L, T> {
+    /// Tries to acquire the lock and dereferences the inner value.
+    #[track_caller]
+    pub fn try_lock(&self) -> Result<ReentrantLockGuard<'_, L, T>, ()> {
+        let id = current_thread_id();
+        if let Some(guard) = self.reacquire(id) {
+            return Ok(guard);
+        }
+
+        let token = self.lock.try_lock()?;
+        Ok(self.acquired(id, token))
+    }
+}
+
+/// A guard that holds the reentrant lock and dereferences the inner value.
+pub struct ReentrantLockGuard<'s, L: RawLock, T> {
+    lock: &'s ReentrantLock<L, T>,
+    // The guard should be dropped by the holding thread.
+    _marker: PhantomData<*const ()>,
+}
+
+unsafe impl<'s, L: RawLock, T: Sync> Sync for ReentrantLockGuard<'s, L, T> {}
+
+impl<'s, L: RawLock, T> ReentrantLockGuard<'s, L, T> {
+    fn new(lock: &'s ReentrantLock<L, T>) -> Self {
+        Self {
+            lock,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'s, L: RawLock, T: fmt::Debug> fmt::Debug for ReentrantLockGuard<'s, L, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReentrantLockGuard")
+            .field("data", &**self)
+            .finish()
+    }
+}
+
+impl<'s, L: RawLock, T> Drop for ReentrantLockGuard<'s, L, T> {
+    fn drop(&mut self) {
+        // SAFETY: the current thread holds the lock, so it has unique access to `state`.
+        let state = unsafe { &mut *self.lock.state.get() };
+        state.0 -= 1;
+        if state.0 != 0 {
+            return;
+        }
+
+        let token = state.1.take().unwrap();
+        self.lock.owner.store(0, Ordering::Relaxed);
+
+        // SAFETY: `token` was given by the `lock()` that made the current thread the holder.
+        unsafe { self.lock.lock.unlock(token) };
+    }
+}
+
+impl<'s, L: RawLock, T> Deref for ReentrantLockGuard<'s, L, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.lock.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use std::thread::scope;
+
+    use super::ReentrantLock;
+    use crate::lock::{McsLock, RawLock, SpinLock};
+
+    fn smoke<L: RawLock>() {
+        const THREADS: usize = 16;
+        const STEPS: usize = 1024;
+
+        let lock = ReentrantLock::<L, Cell<usize>>::new(Cell::new(0));
+
+        scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..STEPS {
+                        let outer = lock.lock();
+                        let inner = lock.lock();
+                        inner.set(inner.get() + 1);
+                        drop(inner);
+                        outer.set(outer.get() + 1);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(lock.into_inner().get(), 2 * THREADS * STEPS);
+    }
+
+    #[test]
+    fn smoke_spinlock() {
+        smoke::<SpinLock>();
+    }
+
+    #[test]
+    fn smoke_mcslock() {
+        smoke::<McsLock>();
+    }
+
+    #[test]
+    fn try_lock() {
+        let lock = ReentrantLock::<SpinLock, usize>::new(0);
+
+        let outer = lock.lock();
+        let inner = lock.try_lock().unwrap();
+        scope(|s| {
+            assert!(s.spawn(|| lock.try_lock().is_err()).join().unwrap());
+        });
+
+        drop(outer);
+        drop(inner);
+        scope(|s| {
+            assert!(s.spawn(|| lock.try_lock().is_ok()).join().unwrap());
+        });
+    }
+}
@@ -0,0 +1,298 @@
+//! Reader-writer locks.
+
+use core::cell::UnsafeCell;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+
+/// Raw reader-writer lock interface.
+pub trait RawRwLock: Default + Send + Sync {
+    /// Raw lock's token type for readers.
+    type ReadToken;
+
+    /// Raw lock's token type for writers.
+    type WriteToken;
+
+    /// Acquires the raw lock for reading.
+    fn read_lock(&self) -> Self::ReadToken;
+
+    /// Releases the raw lock for reading.
+    ///
+    /// # Safety
+    ///
+    /// `read_unlock()` should be called with the token given by the corresponding `read_lock()`.
+    unsafe fn read_unlock(&self, token: Self::ReadToken);
+
+    /// Acquires the raw lock for writing.
+    fn write_lock(&self) -> Self::WriteToken;
+
+    /// Releases the raw lock for writing.
+    ///
+    /// # Safety
+    ///
+    /// `write_unlock()` should be called with the token given by the corresponding `write_lock()`.
+    unsafe fn write_unlock(&self, token: Self::WriteToken);
+}
+
+/// Raw reader-writer lock interface for the try_lock API.
+pub trait RawTryRwLock: RawRwLock {
+    /// Tries to acquire the raw lock for reading.
+    fn try_read_lock(&self) -> Result<Self::ReadToken, ()>;
+
+    /// Tries to acquire the raw lock for writing.
+    fn try_write_lock(&self) -> Result<Self::WriteToken, ()>;
+}
+
+/// A type-safe reader-writer lock.
+#[derive(Debug)]
+pub struct RwLock<L: RawRwLock, T> {
+    lock: L,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<L: RawRwLock, T: Send> Send for RwLock<L, T> {}
+unsafe impl<L: RawRwLock, T: Send + Sync> Sync for RwLock<L, T> {}
+
+impl<L: RawRwLock, T: Default> Default for RwLock<L, T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<L: RawRwLock, T> RwLock<L, T> {
+    /// Creates a new reader-writer lock.
+    pub fn new(data: T) -> Self {
+        Self {
+            lock: L::default(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Destroys the lock and retrieves the lock-protected value.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    /// Dereferences the inner value.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Acquires the lock for reading and dereferences the inner value.
+    pub fn read(&self) -> ReadGuard<'_, L, T> {
+        let token = self.lock.read_lock();
+        ReadGuard {
+            lock: self,
+            token: ManuallyDrop::new(token),
+        }
+    }
+
+    /// Acquires the lock for writing and dereferences the inner value.
+    pub fn write(&self) -> WriteGuard<'_, L, T> {
+        let token = self.lock.write_lock();
+        WriteGuard {
+            lock: self,
+            token: ManuallyDrop::new(token),
+        }
+    }
+}
+
+impl<L: RawTryRwLock, T> RwLock<L, T> {
+    /// Tries to acquire the lock for reading and dereferences the inner value.
+    pub fn try_read(&self) -> Result<ReadGuard<'_, L, T>, ()> {
+        self.lock.try_read_lock().map(|token| ReadGuard {
+            lock: self,
+            token: ManuallyDrop::new(token),
+        })
+    }
+
+    /// Tries to acquire the lock for writing and dereferences the inner value.
+    pub fn try_write(&self) -> Result<WriteGuard<'_, L, T>, ()> {
+        self.lock.try_write_lock().map(|token| WriteGuard {
+            lock: self,
+            token: ManuallyDrop::new(token),
+        })
+    }
+}
+
+/// A guard that holds the lock for reading and dereferences the inner value.
+#[derive(Debug)]
+pub struct ReadGuard<'s, L: RawRwLock, T> {
+    lock: &'s RwLock<L, T>,
+    token: ManuallyDrop<L::ReadToken>,
+}
+
+unsafe impl<'s, L: RawRwLock, T: Sync> Send for ReadGuard<'s, L, T> {}
+unsafe impl<'s, L: RawRwLock, T: Sync> Sync for ReadGuard<'s, L, T> {}
+
+impl<'s, L: RawRwLock, T> Drop for ReadGuard<'s, L, T> {
+    fn drop(&mut self) {
+        // SAFETY: `self.token` is not used anymore in this function, and as we are `drop`ing
+        // `self`, it is not used anymore.
+        let token = unsafe { ManuallyDrop::take(&mut self.token) };
+
+        // SAFETY: since `self` was created with `lock` and it's `token`, the `token` given to
+        // `read_unlock()` is correct.
+        unsafe { self.lock.lock.read_unlock(token) };
+    }
+}
+
+impl<'s, L: RawRwLock, T> Deref for ReadGuard<'s, L, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: Having a `ReadGuard` means the underlying lock is acquired for reading, so no
+        // one is writing to `data`.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+/// A guard that holds the lock for writing and dereferences the inner value.
+#[derive(Debug)]
+pub struct WriteGuard<'s, L: RawRwLock, T> {
+    lock: &'s RwLock<L, T>,
+    token: ManuallyDrop<L::WriteToken>,
+}
+
+unsafe impl<'s, L: RawRwLock, T: Send> Send for WriteGuard<'s, L, T> {}
+unsafe impl<'s, L: RawRwLock, T: Sync> Sync for WriteGuard<'s, L, T> {}
+
+impl<'s, L: RawRwLock, T> Drop for WriteGuard<'s, L, T> {
+    fn drop(&mut self) {
+        // SAFETY: `self.token` is not used anymore in this function, and as we are `drop`ing
+        // `self`, it is not used anymore.
+        let token = unsafe { ManuallyDrop::take(&mut self.token) };
+
+        // SAFETY: since `self` was created with `lock` and it's `token`, the `token` given to
+        // `write_unlock()` is correct.
+        unsafe { self.lock.lock.write_unlock(token) };
+    }
+}
+
+impl<'s, L: RawRwLock, T> Deref for WriteGuard<'s, L, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: Having a `WriteGuard` means the underlying lock is acquired for writing.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'s, L: RawRwLock, T> DerefMut for WriteGuard<'s, L, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: Having a `WriteGuard` means the underlying lock is acquired for writing, so we
+        // have unique access to `data`.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use core::hint;
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use core::time::Duration;
+    use std::collections::HashMap;
+    use std::thread::{self, scope};
+    use std::time::Instant;
+
+    use super::{RawRwLock, RwLock};
+    use crate::test::adt::map;
+
+    pub(crate) fn smoke<L: RawRwLock>() {
+        const LENGTH: usize = 1024;
+        let d = RwLock::<L, Vec<usize>>::new(vec![]);
+
+        scope(|s| {
+            for i in 1..LENGTH {
+                let d = &d;
+                s.spawn(move || {
+                    if i % 4 == 0 {
+                        let mut d = d.write();
+                        d.push(i);
+                    } else {
+                        let d = d.read();
+                        assert!(d.iter().all(|&x| x % 4 == 0));
+                    }
+                });
+            }
+        });
+
+        let mut d = d.into_inner();
+        d.sort_unstable();
+        assert_eq!(d, (4..LENGTH).step_by(4).collect::<Vec<usize>>());
+    }
+
+    pub(crate) fn log_concurrent<L: RawRwLock>() {
+        const THREADS: usize = 16;
+        const STEPS: usize = 4096;
+        map::log_concurrent::<u8, RwLock<L, HashMap<u8, usize>>>(THREADS, STEPS);
+    }
+
+    /// Checks that a reader arriving while a writer waits for the readers gets in only after the
+    /// writer, given whether a writer is waiting for the lock.
+    pub(crate) fn writer_preference<L: RawRwLock>(writer_waiting: impl Fn(&L) -> bool) {
+        let lock = RwLock::<L, usize>::new(0);
+        let reader = lock.read();
+
+        scope(|s| {
+            let _writer = s.spawn(|| *lock.write() += 1);
+            while !writer_waiting(&lock.lock) {
+                thread::yield_now();
+            }
+
+            // Whenever it arrives, the new reader waits for the writer.
+            let new_reader = s.spawn(|| *lock.read());
+            drop(reader);
+            assert_eq!(new_reader.join().unwrap(), 1);
+        });
+    }
+
+    /// Runs writers against readers that keep the lock read-held, and returns the longest time a
+    /// writer waited for the lock. The wait depends on the load of the machine, so it is only
+    /// measured.
+    pub(crate) fn max_writer_wait<L: RawRwLock>() -> Duration {
+        const READERS: usize = 4;
+        const WRITERS: usize = 2;
+        const STEPS: usize = 256;
+
+        let lock = RwLock::<L, usize>::new(0);
+        let done = AtomicBool::new(false);
+
+        let wait = scope(|s| {
+            for _ in 0..READERS {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        let value = lock.read();
+                        for _ in 0..64 {
+                            hint::spin_loop();
+                        }
+                        drop(value);
+                    }
+                });
+            }
+
+            let writers = (0..WRITERS)
+                .map(|_| {
+                    s.spawn(|| {
+                        (0..STEPS)
+                            .map(|_| {
+                                let start = Instant::now();
+                                let mut value = lock.write();
+                                let wait = start.elapsed();
+                                *value += 1;
+                                wait
+                            })
+                            .max()
+                            .unwrap()
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let waits = writers.into_iter().map(|h| h.join()).collect::<Vec<_>>();
+            done.store(true, Ordering::Relaxed);
+            waits.into_iter().map(Result::unwrap).max().unwrap()
+        });
+
+        assert_eq!(lock.into_inner(), WRITERS * STEPS);
+        wait
+    }
+}
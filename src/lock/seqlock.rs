@@ -1,11 +1,17 @@
 //! A sequence lock.
 
-use core::mem;
+use core::cell::UnsafeCell;
+use core::mem::{self, MaybeUninit};
 use core::ops::Deref;
-use core::sync::atomic::{fence, AtomicUsize, Ordering};
+use core::sync::atomic::{fence, AtomicU16, AtomicU32, AtomicU8, AtomicUsize, Ordering};
+use core::time::Duration;
+use std::thread;
+use std::time::Instant;
 
 use crossbeam_utils::Backoff;
 
+use crate::lock::{RawLock, RawTimedLock, RawTryLock};
+
 /// A raw sequence lock.
 #[derive(Debug)]
 pub struct RawSeqLock {
@@ -25,19 +31,7 @@ impl RawSeqLock {
         let backoff = Backoff::new();
 
         loop {
-            let seq = self.seq.load(Ordering::Relaxed);
-            if seq & 1 == 0
-                && self
-                    .seq
-                    .compare_exchange(
-                        seq,
-                        seq.wrapping_add(1),
-                        Ordering::Acquire,
-                        Ordering::Relaxed,
-                    )
-                    .is_ok()
-            {
-                fence(Ordering::Release);
+            if let Ok(seq) = self.try_write_lock() {
                 return seq;
             }
 
@@ -45,6 +39,36 @@ impl RawSeqLock {
         }
     }
 
+    /// Tries to acquire a writer's lock.
+    pub fn try_write_lock(&self) -> Result<usize, ()> {
+        let seq = self.seq.load(Ordering::Relaxed);
+        if seq & 1 != 0 {
+            return Err(());
+        }
+
+        // SAFETY: `seq` is even.
+        unsafe { self.upgrade(seq) }?;
+        Ok(seq)
+    }
+
+    /// Tries to acquire a writer's lock, giving up after the given timeout.
+    pub fn write_lock_for(&self, timeout: Duration) -> Result<usize, ()> {
+        let deadline = Instant::now() + timeout;
+        let backoff = Backoff::new();
+
+        loop {
+            if let Ok(seq) = self.try_write_lock() {
+                return Ok(seq);
+            }
+
+            if Instant::now() >= deadline {
+                return Err(());
+            }
+
+            backoff.snooze();
+        }
+    }
+
     /// Releases a writer's lock.
     pub fn write_unlock(&self, seq: usize) {
         self.seq.store(seq.wrapping_add(2), Ordering::Release);
@@ -71,6 +95,13 @@ impl RawSeqLock {
         seq == self.seq.load(Ordering::Relaxed)
     }
 
+    /// Releases a writer's lock and begins a read at the released sequence number, which is
+    /// returned.
+    pub fn downgrade(&self, seq: usize) -> usize {
+        self.write_unlock(seq);
+        seq.wrapping_add(2)
+    }
+
     /// # Safety
     ///
     /// `seq` must be even.
@@ -93,11 +124,42 @@ impl RawSeqLock {
     }
 }
 
+impl Default for RawSeqLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The writer's side of a raw sequence lock, so that it can be used as a `Lock<RawSeqLock, T>`.
+impl RawLock for RawSeqLock {
+    type Token = usize;
+
+    fn lock(&self) -> usize {
+        self.write_lock()
+    }
+
+    unsafe fn unlock(&self, seq: usize) {
+        self.write_unlock(seq);
+    }
+}
+
+impl RawTryLock for RawSeqLock {
+    fn try_lock(&self) -> Result<usize, ()> {
+        self.try_write_lock()
+    }
+}
+
+impl RawTimedLock for RawSeqLock {
+    fn try_lock_for(&self, timeout: Duration) -> Result<usize, ()> {
+        self.write_lock_for(timeout)
+    }
+}
+
 /// A sequence lock.
 #[derive(Debug)]
 pub struct SeqLock<T> {
     lock: RawSeqLock,
-    data: T,
+    data: UnsafeCell<T>,
 }
 
 /// A writer's lock guard.
@@ -108,7 +170,11 @@ pub struct WriteGuard<'s, T> {
 }
 
 /// A reader's lock guard.
+///
+/// A read critical section should be ended with `finish()` or `upgrade()`, which validate the
+/// read, rather than by dropping the guard.
 #[derive(Debug)]
+#[must_use = "the read should be validated with `finish()`"]
 pub struct ReadGuard<'s, T> {
     lock: &'s SeqLock<T>,
     seq: usize,
@@ -128,18 +194,18 @@ impl<T> SeqLock<T> {
     pub const fn new(data: T) -> Self {
         SeqLock {
             lock: RawSeqLock::new(),
-            data,
+            data: UnsafeCell::new(data),
         }
     }
 
     /// Consumes this seqlock, returning the underlying data.
     pub fn into_inner(self) -> T {
-        self.data
+        self.data.into_inner()
     }
 
     /// Dereferences the inner value.
     pub fn get_mut(&mut self) -> &mut T {
-        &mut self.data
+        self.data.get_mut()
     }
 
     /// Acquires a writer's lock.
@@ -156,10 +222,28 @@ impl<T> SeqLock<T> {
         ReadGuard { lock: self, seq }
     }
 
+    /// Runs `f` in a read critical section, retrying until the read is validated.
+    ///
+    /// # Safety
+    ///
+    /// All reads from the underlying data should be atomic.
+    pub unsafe fn read<F, R>(&self, mut f: F) -> R
+    where
+        F: FnMut(&T) -> R,
+    {
+        loop {
+            if let Some(result) = self.try_read(&mut f) {
+                return result;
+            }
+        }
+    }
+
+    /// Runs `f` in a read critical section once. Returns `None` if the read is invalidated.
+    ///
     /// # Safety
     ///
     /// All reads from the underlying data should be atomic.
-    pub unsafe fn read<F, R>(&self, f: F) -> Option<R>
+    pub unsafe fn try_read<F, R>(&self, f: F) -> Option<R>
     where
         F: FnOnce(&T) -> R,
     {
@@ -174,11 +258,139 @@ impl<T> SeqLock<T> {
     }
 }
 
+/// A `Copy` type without padding bytes, so that its values can be copied word by word with atomic
+/// integer loads and stores.
+///
+/// # Safety
+///
+/// Every byte of every value of the type should be initialized.
+pub unsafe trait NoPadding: Copy {}
+
+macro_rules! no_padding {
+    ($($t:ty),*) => {
+        // SAFETY: primitive types and pointers have no padding.
+        $(unsafe impl NoPadding for $t {})*
+    };
+}
+
+no_padding! {
+    (), bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64
+}
+
+// SAFETY: pointers to sized types are addresses.
+unsafe impl<T> NoPadding for *const T {}
+// SAFETY: pointers to sized types are addresses.
+unsafe impl<T> NoPadding for *mut T {}
+
+// SAFETY: the size of `T` is a multiple of its alignment, so there are no bytes between elements.
+unsafe impl<T: NoPadding, const N: usize> NoPadding for [T; N] {}
+
+impl<T: NoPadding> SeqLock<T> {
+    /// Reads the value, retrying until a consistent snapshot is read.
+    ///
+    /// Unlike `read()`, this is safe because the value is copied out with atomic loads.
+    pub fn load(&self) -> T {
+        loop {
+            let seq = self.lock.read_begin();
+
+            // SAFETY: `T` has no padding, `data` is valid, and all concurrent writes to it by
+            // `store()` are atomic.
+            let value = unsafe { atomic_read(self.data.get()) };
+
+            if self.lock.read_validate(seq) {
+                // SAFETY: no writer intervened, so `value` is a copy of a valid value.
+                return unsafe { value.assume_init() };
+            }
+        }
+    }
+
+    /// Writes the value with atomic stores, so that it can be concurrently read by `load()`.
+    pub fn store(&self, value: T) {
+        let seq = self.lock.write_lock();
+
+        // SAFETY: `T` has no padding, and we hold the writer's lock, so no one else writes to
+        // `data`.
+        unsafe { atomic_write(self.data.get(), value) };
+
+        self.lock.write_unlock(seq);
+    }
+}
+
+/// Copies the value at `src` with per-word atomic loads. The copy may be torn by concurrent
+/// writes, so it should be validated before use.
+///
+/// # Safety
+///
+/// `T` should have no padding bytes, `src` should be valid for reads, and all concurrent writes to
+/// it should be atomic.
+pub(crate) unsafe fn atomic_read<T: Copy>(src: *const T) -> MaybeUninit<T> {
+    let mut dst = MaybeUninit::<T>::uninit();
+
+    macro_rules! copy {
+        ($atomic:ty) => {{
+            let src = src as *const $atomic;
+            let dst = dst.as_mut_ptr() as *mut $atomic;
+            for i in 0..mem::size_of::<T>() / mem::size_of::<$atomic>() {
+                dst.add(i)
+                    .write(<$atomic>::new((*src.add(i)).load(Ordering::Relaxed)));
+            }
+        }};
+    }
+
+    // The size of `T` is a multiple of its alignment, so `T` consists of words of its alignment.
+    match mem::align_of::<T>() {
+        a if a >= mem::align_of::<AtomicUsize>() => copy!(AtomicUsize),
+        a if a >= mem::align_of::<AtomicU32>() => copy!(AtomicU32),
+        a if a >= mem::align_of::<AtomicU16>() => copy!(AtomicU16),
+        _ => copy!(AtomicU8),
+    }
+
+    dst
+}
+
+/// Writes the value to `dst` with per-word atomic stores.
+///
+/// # Safety
+///
+/// `T` should have no padding bytes, `dst` should be valid for writes, and there should be no
+/// concurrent writes to it.
+pub(crate) unsafe fn atomic_write<T: Copy>(dst: *mut T, value: T) {
+    let src = MaybeUninit::new(value);
+
+    macro_rules! copy {
+        ($atomic:ty) => {{
+            let src = src.as_ptr() as *const $atomic;
+            let dst = dst as *const $atomic;
+            for i in 0..mem::size_of::<T>() / mem::size_of::<$atomic>() {
+                (*dst.add(i)).store((*src.add(i)).load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+        }};
+    }
+
+    match mem::align_of::<T>() {
+        a if a >= mem::align_of::<AtomicUsize>() => copy!(AtomicUsize),
+        a if a >= mem::align_of::<AtomicU32>() => copy!(AtomicU32),
+        a if a >= mem::align_of::<AtomicU16>() => copy!(AtomicU16),
+        _ => copy!(AtomicU8),
+    }
+}
+
 impl<'s, T> Deref for WriteGuard<'s, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.lock.data
+        // SAFETY: `UnsafeCell::get()` will not return a null pointer.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'s, T> WriteGuard<'s, T> {
+    /// Releases the writer's lock and begins a read critical section that sees the writes.
+    pub fn downgrade(self) -> ReadGuard<'s, T> {
+        let lock = self.lock;
+        let seq = lock.lock.downgrade(self.seq);
+        mem::forget(self);
+        ReadGuard { lock, seq }
     }
 }
 
@@ -192,7 +404,8 @@ impl<'s, T> Deref for ReadGuard<'s, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.lock.data
+        // SAFETY: `UnsafeCell::get()` will not return a null pointer.
+        unsafe { &*self.lock.data.get() }
     }
 }
 
@@ -207,10 +420,13 @@ impl<'s, T> Clone for ReadGuard<'s, T> {
 
 impl<'s, T> Drop for ReadGuard<'s, T> {
     fn drop(&mut self) {
-        // HACK(@jeehoonkang): we really need linear type here:
-        // https://github.com/rust-lang/rfcs/issues/814
-        // panic!("seqlock::ReadGuard should never drop: use Self::finish() instead");
-        println!("drop...");
+        // We really need linear types here (https://github.com/rust-lang/rfcs/issues/814), so
+        // that a read critical section cannot end without being validated. Until then, catch
+        // misuses in debug builds, unless we are unwinding from a panic anyway.
+        debug_assert!(
+            thread::panicking(),
+            "seqlock::ReadGuard should never drop: use Self::finish() instead"
+        );
     }
 }
 
@@ -225,7 +441,7 @@ impl<'s, T> ReadGuard<'s, T> {
         self.seq = self.lock.lock.read_begin();
     }
 
-    /// Releases the reader's lock.
+    /// Releases the reader's lock. Returns whether the read is valid.
     pub fn finish(self) -> bool {
         let result = self.lock.lock.read_validate(self.seq);
         mem::forget(self);
@@ -245,4 +461,174 @@ impl<'s, T> ReadGuard<'s, T> {
         mem::forget(self);
         result
     }
+
+    /// Tries to upgrade to a writer's lock. If the read is invalidated in the meantime, restarts
+    /// the read critical section and returns it, so that the caller re-reads the data.
+    pub fn upgrade_or_restart(mut self) -> Result<WriteGuard<'s, T>, Self> {
+        // SAFETY: `self.seq` is from `read_begin()` or `downgrade()`, hence even.
+        if unsafe { self.lock.lock.upgrade(self.seq) }.is_ok() {
+            let lock = self.lock;
+            let seq = self.seq;
+            mem::forget(self);
+            return Ok(WriteGuard { lock, seq });
+        }
+
+        self.restart();
+        Err(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::time::Duration;
+    use std::collections::HashMap;
+    use std::thread::scope;
+
+    use super::super::api;
+    use super::{NoPadding, RawSeqLock, SeqLock};
+    use crate::lock::Lock;
+    use crate::test::adt::map;
+
+    const STEPS: usize = 4096;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Stats {
+        count: u64,
+        sum: u64,
+        flags: [u8; 8],
+    }
+
+    // SAFETY: the fields are 8-byte aligned and their sizes are multiples of 8 bytes.
+    unsafe impl NoPadding for Stats {}
+
+    impl Stats {
+        fn new(i: u64) -> Self {
+            Self {
+                count: i,
+                sum: i,
+                flags: [i as u8; 8],
+            }
+        }
+    }
+
+    #[test]
+    fn load_store() {
+        const THREADS: usize = 4;
+
+        let lock = SeqLock::new(Stats::new(0));
+
+        scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for i in 0..STEPS as u64 {
+                        lock.store(Stats::new(i));
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..STEPS {
+                        let stats = lock.load();
+                        assert_eq!(stats, Stats::new(stats.count));
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn read() {
+        let lock = SeqLock::new(AtomicUsize::new(0));
+
+        scope(|s| {
+            s.spawn(|| {
+                for _ in 0..STEPS {
+                    let guard = lock.write_lock();
+                    guard.fetch_add(1, Ordering::Relaxed);
+                    guard.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+
+            for _ in 0..STEPS {
+                // SAFETY: `AtomicUsize` is read atomically.
+                let value = unsafe { lock.read(|v| v.load(Ordering::Relaxed)) };
+                assert_eq!(value % 2, 0);
+            }
+        });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic = "should never drop"]
+    fn drop_read_guard() {
+        let lock = SeqLock::new(0usize);
+
+        // SAFETY: `usize` is not concurrently written.
+        let _guard = unsafe { lock.read_lock() };
+    }
+
+    #[test]
+    fn load_store_bytes() {
+        let lock = SeqLock::new([1u8, 2, 3]);
+        assert_eq!(lock.load(), [1, 2, 3]);
+        lock.store([4, 5, 6]);
+        assert_eq!(lock.load(), [4, 5, 6]);
+    }
+
+    #[test]
+    fn smoke_raw_lock() {
+        api::tests::smoke::<RawSeqLock>();
+    }
+
+    #[test]
+    fn log_concurrent_raw_lock() {
+        const THREADS: usize = 16;
+        map::log_concurrent::<u8, Lock<RawSeqLock, HashMap<u8, usize>>>(THREADS, STEPS);
+    }
+
+    #[test]
+    fn try_write_lock() {
+        let lock = RawSeqLock::new();
+
+        let seq = lock.try_write_lock().unwrap();
+        assert!(lock.try_write_lock().is_err());
+        assert!(lock.write_lock_for(Duration::from_millis(10)).is_err());
+        lock.write_unlock(seq);
+
+        let seq = lock.write_lock_for(Duration::from_millis(10)).unwrap();
+        lock.write_unlock(seq);
+    }
+
+    #[test]
+    fn downgrade() {
+        let lock = SeqLock::new(AtomicUsize::new(0));
+
+        let writer = lock.write_lock();
+        writer.store(1, Ordering::Relaxed);
+        let reader = writer.downgrade();
+        assert_eq!(reader.load(Ordering::Relaxed), 1);
+        assert!(reader.validate());
+
+        lock.write_lock().store(2, Ordering::Relaxed);
+        assert!(!reader.finish());
+    }
+
+    #[test]
+    fn upgrade_or_restart() {
+        let lock = SeqLock::new(AtomicUsize::new(0));
+
+        // SAFETY: `AtomicUsize` is read atomically.
+        let (r1, r2) = unsafe { (lock.read_lock(), lock.read_lock()) };
+
+        let w1 = r1.upgrade_or_restart().unwrap();
+        w1.store(1, Ordering::Relaxed);
+        drop(w1);
+
+        // `r2` was invalidated by `w1`, so it is restarted and sees the write.
+        let r2 = r2.upgrade_or_restart().unwrap_err();
+        assert_eq!(r2.load(Ordering::Relaxed), 1);
+
+        let w2 = r2.upgrade_or_restart().unwrap();
+        w2.store(2, Ordering::Relaxed);
+        assert!(w2.downgrade().finish());
+    }
 }
@@ -1,28 +1,29 @@
+use core::marker::PhantomData;
 use core::sync::atomic::{AtomicBool, Ordering};
 
-use crossbeam_utils::Backoff;
-
 use crate::lock::*;
 
-/// A spin lock.
+/// A spin lock that waits with the backoff strategy `B` while the lock is held.
 #[derive(Debug)]
-pub struct SpinLock {
+pub struct SpinLock<B: BackoffPolicy = ExponentialBackoff> {
     inner: AtomicBool,
+    _marker: PhantomData<fn() -> B>,
 }
 
-impl Default for SpinLock {
+impl<B: BackoffPolicy> Default for SpinLock<B> {
     fn default() -> Self {
         Self {
             inner: AtomicBool::new(false),
+            _marker: PhantomData,
         }
     }
 }
 
-impl RawLock for SpinLock {
+impl<B: BackoffPolicy> RawLock for SpinLock<B> {
     type Token = ();
 
     fn lock(&self) {
-        let backoff = Backoff::new();
+        let mut backoff = B::default();
 
         while self
             .inner
@@ -38,7 +39,7 @@ impl RawLock for SpinLock {
     }
 }
 
-impl RawTryLock for SpinLock {
+impl<B: BackoffPolicy> RawTryLock for SpinLock<B> {
     fn try_lock(&self) -> Result<(), ()> {
         self.inner
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
@@ -51,9 +52,25 @@ impl RawTryLock for SpinLock {
 mod tests {
     use super::super::api;
     use super::spinlock::SpinLock;
+    use crate::lock::{BoundedSpinBackoff, ParkBackoff, YieldBackoff};
 
     #[test]
     fn smoke() {
         api::tests::smoke::<SpinLock>();
     }
+
+    #[test]
+    fn smoke_bounded_spin() {
+        api::tests::smoke::<SpinLock<BoundedSpinBackoff>>();
+    }
+
+    #[test]
+    fn smoke_yield() {
+        api::tests::smoke::<SpinLock<YieldBackoff>>();
+    }
+
+    #[test]
+    fn smoke_park() {
+        api::tests::smoke::<SpinLock<ParkBackoff>>();
+    }
 }
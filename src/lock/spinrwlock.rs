@@ -0,0 +1,109 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crossbeam_utils::Backoff;
+
+use crate::lock::*;
+
+const WRITER: usize = 1;
+const READER: usize = 2;
+
+/// A spin reader-writer lock.
+///
+/// The lowest bit of the state is set while a writer holds the lock, and the other bits count the
+/// readers holding the lock.
+#[derive(Debug)]
+pub struct SpinRwLock {
+    inner: AtomicUsize,
+}
+
+impl Default for SpinRwLock {
+    fn default() -> Self {
+        Self {
+            inner: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl RawRwLock for SpinRwLock {
+    type ReadToken = ();
+    type WriteToken = ();
+
+    fn read_lock(&self) {
+        let backoff = Backoff::new();
+
+        while self.try_read_lock().is_err() {
+            backoff.snooze();
+        }
+    }
+
+    unsafe fn read_unlock(&self, _token: ()) {
+        self.inner.fetch_sub(READER, Ordering::Release);
+    }
+
+    fn write_lock(&self) {
+        let backoff = Backoff::new();
+
+        while self.try_write_lock().is_err() {
+            backoff.snooze();
+        }
+    }
+
+    unsafe fn write_unlock(&self, _token: ()) {
+        self.inner.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+impl RawTryRwLock for SpinRwLock {
+    fn try_read_lock(&self) -> Result<(), ()> {
+        let state = self.inner.load(Ordering::Relaxed);
+        if state & WRITER != 0 {
+            return Err(());
+        }
+
+        self.inner
+            .compare_exchange(state, state + READER, Ordering::Acquire, Ordering::Relaxed)
+            .map(|_| ())
+            .map_err(|_| ())
+    }
+
+    fn try_write_lock(&self) -> Result<(), ()> {
+        self.inner
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .map(|_| ())
+            .map_err(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::rwlock;
+    use super::spinrwlock::SpinRwLock;
+    use crate::lock::RwLock;
+
+    #[test]
+    fn smoke() {
+        rwlock::tests::smoke::<SpinRwLock>();
+    }
+
+    #[test]
+    fn log_concurrent() {
+        rwlock::tests::log_concurrent::<SpinRwLock>();
+    }
+
+    #[test]
+    fn try_lock() {
+        let lock = RwLock::<SpinRwLock, usize>::new(0);
+
+        let r1 = lock.read();
+        let r2 = lock.try_read().unwrap();
+        assert!(lock.try_write().is_err());
+        drop((r1, r2));
+
+        let w = lock.write();
+        assert!(lock.try_read().is_err());
+        assert!(lock.try_write().is_err());
+        drop(w);
+
+        assert!(lock.try_write().is_ok());
+    }
+}
@@ -0,0 +1,219 @@
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::mem::ManuallyDrop;
+use std::collections::hash_map::DefaultHasher;
+
+use crossbeam_utils::CachePadded;
+
+use crate::lock::*;
+
+/// A table of `N` raw locks, each of which protects all objects whose address or hash maps to it.
+///
+/// This gives fine-grained locking for many small objects without embedding a lock in each of
+/// them. Since distinct objects may map to the same lock, a thread holding a lock of the table
+/// should acquire another one only if its index is greater, e.g. by ordering them with
+/// `index_of_addr()` and `index_of_hash()` and acquiring them with `lock_index()`.
+#[derive(Debug)]
+pub struct StripedLocks<L: RawLock, const N: usize> {
+    locks: [CachePadded<L>; N],
+}
+
+/// A guard that holds one of the locks of a striped lock table.
+pub struct StripeGuard<'s, L: RawLock> {
+    lock: &'s L,
+    index: usize,
+    token: ManuallyDrop<L::Token>,
+}
+
+impl<L: RawLock, const N: usize> Default for StripedLocks<L, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L: RawLock, const N: usize> StripedLocks<L, N> {
+    /// Creates a new striped lock table.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N` is 0.
+    pub fn new() -> Self {
+        assert!(N > 0, "a striped lock table should have at least one lock");
+
+        Self {
+            locks: core::array::from_fn(|_| CachePadded::new(L::default())),
+        }
+    }
+
+    /// Returns the index of the lock for the given hash.
+    fn index(hash: u64) -> usize {
+        // Fibonacci hashing spreads consecutive hashes, e.g. addresses, over the table.
+        (hash.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize % N
+    }
+
+    /// Returns the index of the lock for the object at the given address.
+    pub fn index_of_addr<T: ?Sized>(&self, ptr: *const T) -> usize {
+        Self::index(ptr as *const () as usize as u64)
+    }
+
+    /// Returns the index of the lock for the given key.
+    pub fn index_of_hash<K: Hash + ?Sized>(&self, key: &K) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        Self::index(hasher.finish())
+    }
+
+    /// Acquires the lock of the given index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than `N`.
+    pub fn lock_index(&self, index: usize) -> StripeGuard<'_, L> {
+        let lock = &*self.locks[index];
+        StripeGuard {
+            lock,
+            index,
+            token: ManuallyDrop::new(lock.lock()),
+        }
+    }
+
+    /// Acquires the lock for the object at the given address.
+    pub fn lock_addr<T: ?Sized>(&self, ptr: *const T) -> StripeGuard<'_, L> {
+        self.lock_index(self.index_of_addr(ptr))
+    }
+
+    /// Acquires the lock for the given key.
+    pub fn lock_hash<K: Hash + ?Sized>(&self, key: &K) -> StripeGuard<'_, L> {
+        self.lock_index(self.index_of_hash(key))
+    }
+}
+
+impl<L: RawTryLock, const N: usize> StripedLocks<L, N> {
+    /// Tries to acquire the lock of the given index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than `N`.
+    pub fn try_lock_index(&self, index: usize) -> Result<StripeGuard<'_, L>, ()> {
+        let lock = &*self.locks[index];
+        lock.try_lock().map(|token| StripeGuard {
+            lock,
+            index,
+            token: ManuallyDrop::new(token),
+        })
+    }
+
+    /// Tries to acquire the lock for the object at the given address.
+    pub fn try_lock_addr<T: ?Sized>(&self, ptr: *const T) -> Result<StripeGuard<'_, L>, ()> {
+        self.try_lock_index(self.index_of_addr(ptr))
+    }
+
+    /// Tries to acquire the lock for the given key.
+    pub fn try_lock_hash<K: Hash + ?Sized>(&self, key: &K) -> Result<StripeGuard<'_, L>, ()> {
+        self.try_lock_index(self.index_of_hash(key))
+    }
+}
+
+impl<'s, L: RawLock> StripeGuard<'s, L> {
+    /// Returns the index of the held lock in its table.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl<'s, L: RawLock> Drop for StripeGuard<'s, L> {
+    fn drop(&mut self) {
+        // SAFETY: `self.token` is not used anymore in this function, and as we are `drop`ing
+        // `self`, it is not used anymore.
+        let token = unsafe { ManuallyDrop::take(&mut self.token) };
+
+        // SAFETY: since `self` was created with `lock` and it's `token`, the `token` given to
+        // `unlock()` is correct.
+        unsafe { self.lock.unlock(token) };
+    }
+}
+
+impl<'s, L: RawLock> fmt::Debug for StripeGuard<'s, L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StripeGuard")
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::UnsafeCell;
+    use std::thread::scope;
+
+    use rand::Rng;
+
+    use super::StripedLocks;
+    use crate::lock::{McsLock, RawLock, SpinLock};
+
+    /// Counters that are only accessed while holding their locks in a striped lock table.
+    struct Counters(Vec<UnsafeCell<usize>>);
+
+    unsafe impl Sync for Counters {}
+
+    impl Counters {
+        fn get(&self, index: usize) -> &UnsafeCell<usize> {
+            &self.0[index]
+        }
+    }
+
+    fn smoke<L: RawLock>() {
+        const THREADS: usize = 8;
+        const STEPS: usize = 4096;
+        const COUNTERS: usize = 1 << 16;
+
+        let locks = StripedLocks::<L, 64>::new();
+        let counters = Counters((0..COUNTERS).map(|_| UnsafeCell::new(0)).collect());
+
+        scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    let mut rng = rand::thread_rng();
+                    for _ in 0..STEPS {
+                        let counter = counters.get(rng.gen_range(0..COUNTERS));
+                        let _guard = locks.lock_addr(counter);
+                        // SAFETY: we hold the lock for `counter`.
+                        unsafe { *counter.get() += 1 };
+                    }
+                });
+            }
+        });
+
+        let total = counters.0.into_iter().map(UnsafeCell::into_inner);
+        assert_eq!(total.sum::<usize>(), THREADS * STEPS);
+    }
+
+    #[test]
+    fn smoke_spinlock() {
+        smoke::<SpinLock>();
+    }
+
+    #[test]
+    fn smoke_mcslock() {
+        smoke::<McsLock>();
+    }
+
+    #[test]
+    fn index() {
+        let locks = StripedLocks::<SpinLock, 7>::new();
+        let values = [0u8; 64];
+
+        for value in &values {
+            assert!(locks.index_of_addr(value) < 7);
+            assert_eq!(locks.index_of_addr(value), locks.index_of_addr(value));
+        }
+        assert_eq!(locks.index_of_hash("key"), locks.index_of_hash("key"));
+
+        let guard = locks.lock_hash("key");
+        assert_eq!(guard.index(), locks.index_of_hash("key"));
+        assert!(locks.try_lock_hash("key").is_err());
+        assert!(locks.try_lock_index((guard.index() + 1) % 7).is_ok());
+        drop(guard);
+        assert!(locks.try_lock_hash("key").is_ok());
+    }
+}
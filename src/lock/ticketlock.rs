@@ -0,0 +1,79 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crossbeam_utils::{Backoff, CachePadded};
+
+use crate::lock::*;
+
+/// A ticket lock.
+///
+/// Threads are served in the FIFO order in which they take a ticket.
+#[derive(Debug)]
+pub struct TicketLock {
+    curr: CachePadded<AtomicUsize>,
+    next: CachePadded<AtomicUsize>,
+}
+
+impl Default for TicketLock {
+    fn default() -> Self {
+        Self {
+            curr: CachePadded::new(AtomicUsize::new(0)),
+            next: CachePadded::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl RawLock for TicketLock {
+    type Token = usize;
+
+    fn lock(&self) -> usize {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        let backoff = Backoff::new();
+
+        while self.curr.load(Ordering::Acquire) != ticket {
+            backoff.snooze();
+        }
+
+        ticket
+    }
+
+    unsafe fn unlock(&self, ticket: usize) {
+        self.curr.store(ticket.wrapping_add(1), Ordering::Release);
+    }
+}
+
+impl RawTryLock for TicketLock {
+    fn try_lock(&self) -> Result<usize, ()> {
+        // Only take a ticket if it would be served right away, i.e. nobody holds or waits for the
+        // lock.
+        let ticket = self.curr.load(Ordering::Acquire);
+        self.next
+            .compare_exchange(
+                ticket,
+                ticket.wrapping_add(1),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .map_err(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::api;
+    use super::ticketlock::TicketLock;
+    use crate::lock::Lock;
+
+    #[test]
+    fn smoke() {
+        api::tests::smoke::<TicketLock>();
+    }
+
+    #[test]
+    fn try_lock() {
+        let lock = Lock::<TicketLock, usize>::new(0);
+        let guard = lock.lock();
+        assert!(lock.try_lock().is_err());
+        drop(guard);
+        assert!(lock.try_lock().is_ok());
+    }
+}
@@ -0,0 +1,143 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crossbeam_utils::Backoff;
+
+use crate::lock::*;
+
+const WRITER: usize = 1;
+const READER: usize = 2;
+
+/// A writer-preferring spin reader-writer lock.
+///
+/// New readers wait while any writer is waiting for the lock, so that continuous readers do not
+/// starve writers. In turn, continuous writers may starve readers.
+#[derive(Debug)]
+pub struct WriterPrefRwLock {
+    /// The lowest bit is set while a writer holds the lock, and the other bits count the readers
+    /// holding the lock.
+    state: AtomicUsize,
+    /// The number of writers holding or waiting for the lock.
+    writers: AtomicUsize,
+}
+
+impl Default for WriterPrefRwLock {
+    fn default() -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            writers: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl RawRwLock for WriterPrefRwLock {
+    type ReadToken = ();
+    type WriteToken = ();
+
+    fn read_lock(&self) {
+        let backoff = Backoff::new();
+
+        while self.try_read_lock().is_err() {
+            backoff.snooze();
+        }
+    }
+
+    unsafe fn read_unlock(&self, _token: ()) {
+        self.state.fetch_sub(READER, Ordering::Release);
+    }
+
+    fn write_lock(&self) {
+        self.writers.fetch_add(1, Ordering::Relaxed);
+
+        let backoff = Backoff::new();
+        while self
+            .state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            backoff.snooze();
+        }
+    }
+
+    unsafe fn write_unlock(&self, _token: ()) {
+        self.state.fetch_and(!WRITER, Ordering::Release);
+        self.writers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl RawTryRwLock for WriterPrefRwLock {
+    fn try_read_lock(&self) -> Result<(), ()> {
+        if self.writers.load(Ordering::Relaxed) != 0 {
+            return Err(());
+        }
+
+        let state = self.state.load(Ordering::Relaxed);
+        if state & WRITER != 0 {
+            return Err(());
+        }
+
+        self.state
+            .compare_exchange(state, state + READER, Ordering::Acquire, Ordering::Relaxed)
+            .map(|_| ())
+            .map_err(|_| ())
+    }
+
+    fn try_write_lock(&self) -> Result<(), ()> {
+        self.writers.fetch_add(1, Ordering::Relaxed);
+
+        let result = self
+            .state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed);
+        if result.is_err() {
+            self.writers.fetch_sub(1, Ordering::Relaxed);
+        }
+        result.map(|_| ()).map_err(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::Ordering;
+
+    use super::super::rwlock;
+    use super::writerprefrwlock::WriterPrefRwLock;
+    use crate::lock::RwLock;
+
+    #[test]
+    fn smoke() {
+        rwlock::tests::smoke::<WriterPrefRwLock>();
+    }
+
+    #[test]
+    fn log_concurrent() {
+        rwlock::tests::log_concurrent::<WriterPrefRwLock>();
+    }
+
+    #[test]
+    fn max_writer_wait() {
+        let _wait = rwlock::tests::max_writer_wait::<WriterPrefRwLock>();
+    }
+
+    #[test]
+    fn writer_preference() {
+        rwlock::tests::writer_preference::<WriterPrefRwLock>(|lock| {
+            lock.writers.load(Ordering::Relaxed) != 0
+        });
+    }
+
+    #[test]
+    fn try_lock() {
+        let lock = RwLock::<WriterPrefRwLock, usize>::new(0);
+
+        let r1 = lock.read();
+        let r2 = lock.try_read().unwrap();
+        assert!(lock.try_write().is_err());
+        drop((r1, r2));
+
+        let w = lock.write();
+        assert!(lock.try_read().is_err());
+        assert!(lock.try_write().is_err());
+        drop(w);
+
+        assert!(lock.try_write().is_ok());
+    }
+}